@@ -2,6 +2,12 @@ VERSION := 0.0.4
 
 ARCH ?= $(shell uname -m)
 
+# number of CPUs given to QEMU
+SMP  ?= 4
+
+# cargo features, e.g. FEATURES="heap_debug strict_wx"
+FEATURES ?=
+
 LFLAGS += 
 RFLAGS +=
 
@@ -17,7 +17,9 @@
           , use_spin
           , use_extern_macros
           , use_nested_groups
-          , unique )]
+          , unique
+          , fnbox
+          , conservative_impl_trait )]
 
 #![allow( unknown_lints
         , empty_loop
@@ -49,9 +51,17 @@ use kernel::{
     vga ,
 };
 
+#[cfg(feature = "heap_debug")]
+use kernel::mem::alloc::debug::DebugAllocator;
+
+#[cfg(not(feature = "heap_debug"))]
 #[global_allocator]
 static HEAP_ALLOCATOR : HeapAllocator = HeapAllocator::blank();
 
+#[cfg(feature = "heap_debug")]
+#[global_allocator]
+static HEAP_ALLOCATOR : DebugAllocator = DebugAllocator::blank();
+
 #[lang = "panic_fmt"]
 #[cfg(not(test))]
 #[no_mangle]
@@ -132,15 +142,36 @@ pub extern "C" fn main(_mb_addr : usize) {
 
     vga::clear_screen();
 
+    kernel::cpu::init();
+
     bits::init();
+
+    kernel::fpu::init();
     
-    let memory_controller = &mut mem::init(unsafe { kernel::boot::load(_mb_addr) });
+    mem::init(unsafe { kernel::boot::load(_mb_addr) });
 
     unsafe {
-        HEAP_ALLOCATOR.lock().init(globals::HEAP_START, globals::HEAP_START + globals::HEAP_SIZE);
+        HEAP_ALLOCATOR.lock().init(globals::HEAP_START, globals::HEAP_SIZE);
     }
 
-    kernel::interrupt::init(memory_controller);
+    kernel::interrupt::init(&mut mem::controller());
+
+    kernel::syscall::init();
+
+    kernel::process::init();
+
+    kernel::thread::init();
+
+    unsafe { kernel::interrupt::enable() };
+
+    kernel::smp::init();
+
+    println!("{}", mem::heap_stats());
+
+    #[cfg(feature = "heap_debug")]
+    {
+        if mem::heap_check() > 0 { mem::heap_dump(); }
+    }
 
     x86_64::instructions::interrupts::int3();
 
@@ -12,13 +12,30 @@ pub fn enable_nxe_bit() {
     }
 }
 
+pub fn enable_syscall_bit() {
+    use x86_64::registers::msr::{IA32_EFER, rdmsr, wrmsr};
+
+    let sce_bit = 1 << 0;
+    unsafe {
+        let efer = rdmsr(IA32_EFER);
+        wrmsr(IA32_EFER, efer | sce_bit);
+    }
+}
+
 pub fn enable_write_protect_bit() {
     use x86_64::registers::control_regs::{cr0, cr0_write, Cr0};
 
     unsafe { cr0_write(cr0() | Cr0::WRITE_PROTECT) };
 }
 
+/// Turns on the paging features the kernel relies on. The page 
+/// tables use `NO_EXECUTE`, so a CPU without NX can't go on.
 pub fn init() {
+    use kernel::{ cpu, mem::paging::cache };
+
+    assert!(cpu::has(cpu::NX), "the CPU has no no-execute support");
+
     enable_nxe_bit();
     enable_write_protect_bit();
+    cache::init();
 }
@@ -0,0 +1,169 @@
+// -*- mode: rust; -*-
+
+//! # CPU identification
+//!
+//! The CPUID leaves are read once, by `init` or the first `info`, and 
+//! kept as a `Features` set with the vendor and brand strings. All 
+//! processors are assumed to be alike, so the bootstrap processor's 
+//! view is used everywhere.
+
+use core::str;
+
+use spin::Once;
+
+bitflags! {
+    pub flags Features : u64 {
+        const FPU          = 1 <<  0,
+        const PAE          = 1 <<  1,
+        const APIC         = 1 <<  2,
+        const PGE          = 1 <<  3,
+        const PAT          = 1 <<  4,
+        const FXSR         = 1 <<  5,
+        const SSE          = 1 <<  6,
+        const SSE2         = 1 <<  7,
+        const SSE3         = 1 <<  8,
+        const SSSE3        = 1 <<  9,
+        const SSE4_1       = 1 << 10,
+        const SSE4_2       = 1 << 11,
+        const PCID         = 1 << 12,
+        const X2APIC       = 1 << 13,
+        const TSC_DEADLINE = 1 << 14,
+        const XSAVE        = 1 << 15,
+        const AVX          = 1 << 16,
+        const RDRAND       = 1 << 17,
+        const SMEP         = 1 << 18,
+        const SMAP         = 1 << 19,
+        const INVPCID      = 1 << 20,
+        const SYSCALL      = 1 << 21,
+        const NX           = 1 << 22,
+        const PAGE_1G      = 1 << 23,
+        const LONG_MODE    = 1 << 24,
+    }
+}
+
+/// Register of a CPUID result.
+#[derive(Clone, Copy)]
+enum Reg { Ebx, Ecx, Edx }
+
+const LEAF_STD : u32 = 0x0000_0001;
+const LEAF_EXT : u32 = 0x0000_0007;
+const LEAF_AMD : u32 = 0x8000_0001;
+
+/// Where each feature is reported: leaf, register and bit, with its name.
+static BITS : [(Features, u32, Reg, u32, &'static str) ; 25] = [
+    (FPU,          LEAF_STD, Reg::Edx,  0, "fpu"),
+    (PAE,          LEAF_STD, Reg::Edx,  6, "pae"),
+    (APIC,         LEAF_STD, Reg::Edx,  9, "apic"),
+    (PGE,          LEAF_STD, Reg::Edx, 13, "pge"),
+    (PAT,          LEAF_STD, Reg::Edx, 16, "pat"),
+    (FXSR,         LEAF_STD, Reg::Edx, 24, "fxsr"),
+    (SSE,          LEAF_STD, Reg::Edx, 25, "sse"),
+    (SSE2,         LEAF_STD, Reg::Edx, 26, "sse2"),
+    (SSE3,         LEAF_STD, Reg::Ecx,  0, "sse3"),
+    (SSSE3,        LEAF_STD, Reg::Ecx,  9, "ssse3"),
+    (SSE4_1,       LEAF_STD, Reg::Ecx, 19, "sse4.1"),
+    (SSE4_2,       LEAF_STD, Reg::Ecx, 20, "sse4.2"),
+    (PCID,         LEAF_STD, Reg::Ecx, 17, "pcid"),
+    (X2APIC,       LEAF_STD, Reg::Ecx, 21, "x2apic"),
+    (TSC_DEADLINE, LEAF_STD, Reg::Ecx, 24, "tsc-deadline"),
+    (XSAVE,        LEAF_STD, Reg::Ecx, 26, "xsave"),
+    (AVX,          LEAF_STD, Reg::Ecx, 28, "avx"),
+    (RDRAND,       LEAF_STD, Reg::Ecx, 30, "rdrand"),
+    (SMEP,         LEAF_EXT, Reg::Ebx,  7, "smep"),
+    (SMAP,         LEAF_EXT, Reg::Ebx, 20, "smap"),
+    (INVPCID,      LEAF_EXT, Reg::Ebx, 10, "invpcid"),
+    (SYSCALL,      LEAF_AMD, Reg::Edx, 11, "syscall"),
+    (NX,           LEAF_AMD, Reg::Edx, 20, "nx"),
+    (PAGE_1G,      LEAF_AMD, Reg::Edx, 26, "1g-pages"),
+    (LONG_MODE,    LEAF_AMD, Reg::Edx, 29, "long-mode"),
+];
+
+pub struct Info {
+    vendor       : [u8 ; 12],
+    brand        : [u8 ; 48],
+    pub features : Features,
+    /// highest standard and extended leaves
+    pub max_leaf     : u32,
+    pub max_ext_leaf : u32,
+}
+
+impl Info {
+    pub fn vendor(&self) -> &str {
+        str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    /// Brand string without its padding, empty if the CPU has none.
+    pub fn brand(&self) -> &str {
+        let end = self.brand.iter().position(|&b| b == 0).unwrap_or(self.brand.len());
+        str::from_utf8(&self.brand[..end]).unwrap_or("").trim()
+    }
+}
+
+static INFO : Once<Info> = Once::new();
+
+/// Runs CPUID on `leaf` and `sub`, returns eax, ebx, ecx and edx.
+pub fn cpuid(leaf : u32, sub : u32) -> (u32, u32, u32, u32) {
+    let (a, b, c, d) : (u32, u32, u32, u32);
+    unsafe {
+        asm!("cpuid" 
+             : "={eax}"(a), "={ebx}"(b), "={ecx}"(c), "={edx}"(d) 
+             : "{eax}"(leaf), "{ecx}"(sub) 
+             :: "volatile");
+    }
+    (a, b, c, d)
+}
+
+fn put(buf : &mut [u8], regs : &[u32]) {
+    for (i, r) in regs.iter().enumerate() {
+        for j in 0..4 { buf[i * 4 + j] = (r >> (j * 8)) as u8; }
+    }
+}
+
+fn detect() -> Info {
+    let (max_leaf, b, c, d) = cpuid(0, 0);
+    let (max_ext_leaf, ..)  = cpuid(0x8000_0000, 0);
+
+    let mut vendor = [0 ; 12];
+    put(&mut vendor, &[b, d, c]);
+
+    let mut brand = [0 ; 48];
+    if max_ext_leaf >= 0x8000_0004 {
+        for (i, leaf) in (0x8000_0002..0x8000_0005).enumerate() {
+            let (a, b, c, d) = cpuid(leaf, 0);
+            put(&mut brand[i * 16..], &[a, b, c, d]);
+        }
+    }
+
+    let available = |leaf : u32| if leaf & 0x8000_0000 != 0 { leaf <= max_ext_leaf } else { leaf <= max_leaf };
+
+    let features = BITS.iter()
+        .filter(|&&(_, leaf, _, _, _)| available(leaf))
+        .filter(|&&(_, leaf, reg, bit, _)| {
+            let (_, b, c, d) = cpuid(leaf, 0);
+            let v = match reg { Reg::Ebx => b, Reg::Ecx => c, Reg::Edx => d };
+            v & (1 << bit) != 0
+        })
+        .fold(Features::empty(), |fs, &(f, ..)| fs | f);
+
+    Info { vendor, brand, features, max_leaf, max_ext_leaf }
+}
+
+/// Returns what CPUID reported, querying it on the first call.
+pub fn info() -> &'static Info {
+    INFO.call_once(detect)
+}
+
+/// Returns `true` if the CPU has all features of `f`.
+pub fn has(f : Features) -> bool {
+    info().features.contains(f)
+}
+
+/// Reads CPUID and prints the vendor, brand and features.
+pub fn init() {
+    let i = info();
+
+    println!("cpu: {} {}", i.vendor(), i.brand());
+    print!("cpu features:");
+    BITS.iter().filter(|b| i.features.contains(b.0)).for_each(|b| print!(" {}", b.4));
+    println!();
+}
@@ -0,0 +1,199 @@
+// -*- mode: rust; -*-
+
+//! # ELF64 executables
+//!
+//! Unlike `kernel::boot::elf`, which reads the section headers 
+//! handed over by GRUB, this parses program headers of whole 
+//! executable images.
+
+use core::{ mem, slice };
+
+const ELF_MAGIC   : [u8 ; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64  : u8  = 2;
+const ELFDATA2LSB : u8  = 1;
+const EV_CURRENT  : u8  = 1;
+const ET_EXEC     : u16 = 2;
+const EM_X86_64   : u16 = 62;
+
+pub const PT_NULL      : u32 = 0;
+pub const PT_LOAD      : u32 = 1;
+pub const PT_DYNAMIC   : u32 = 2;
+pub const PT_INTERP    : u32 = 3;
+pub const PT_PHDR      : u32 = 6;
+pub const PT_GNU_STACK : u32 = 0x6474_E551;
+
+pub const PF_X : u32 = 1 << 0;
+pub const PF_W : u32 = 1 << 1;
+pub const PF_R : u32 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// image is shorter than the headers say
+    Truncated,
+    /// image isn't 8 byte aligned
+    Misaligned,
+    BadMagic,
+    /// not a 64 bit little endian image
+    BadClass,
+    BadVersion,
+    /// not an executable
+    BadType,
+    /// not an x86_64 image
+    BadMachine,
+    /// dynamically linked images are unsupported
+    Interpreter,
+    /// segment lies outside of user space or the image
+    BadSegment,
+    /// arguments don't fit on the initial stack page
+    ArgsTooLong,
+    NoMemory,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Header {
+    e_ident     : [u8 ; 16],
+    e_type      : u16,
+    e_machine   : u16,
+    e_version   : u32,
+    e_entry     : u64,
+    e_phoff     : u64,
+    e_shoff     : u64,
+    e_flags     : u32,
+    e_ehsize    : u16,
+    e_phentsize : u16,
+    e_phnum     : u16,
+    e_shentsize : u16,
+    e_shnum     : u16,
+    e_shstrndx  : u16,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct ProgramHeader {
+    p_type   : u32,
+    p_flags  : u32,
+    p_offset : u64,
+    p_vaddr  : u64,
+    p_paddr  : u64,
+    p_filesz : u64,
+    p_memsz  : u64,
+    p_align  : u64,
+}
+
+impl ProgramHeader {
+    pub default fn typ(&self) -> u32 {
+        self.p_type
+    }
+
+    pub default fn flags(&self) -> u32 {
+        self.p_flags
+    }
+
+    pub default fn offset(&self) -> usize {
+        self.p_offset as usize
+    }
+
+    pub default fn vaddr(&self) -> usize {
+        self.p_vaddr as usize
+    }
+
+    pub default fn file_size(&self) -> usize {
+        self.p_filesz as usize
+    }
+
+    pub default fn mem_size(&self) -> usize {
+        self.p_memsz as usize
+    }
+}
+
+/// Validated ELF64 image.
+pub struct Elf<'a> {
+    data : &'a [u8],
+    h    : &'a Header,
+    ph   : &'a [ProgramHeader],
+}
+
+impl<'a> Elf<'a> {
+    /// Validates the header and the program header table of `data`.
+    pub fn parse(data : &'a [u8]) -> Result<Elf<'a>, Error> {
+        if data.as_ptr() as usize % mem::align_of::<Header>() != 0 { return Err(Error::Misaligned); }
+        if data.len() < mem::size_of::<Header>() { return Err(Error::Truncated); }
+
+        let h = unsafe { &*(data.as_ptr() as *const Header) };
+
+        if h.e_ident[0..4] != ELF_MAGIC { return Err(Error::BadMagic); }
+        if h.e_ident[4] != ELFCLASS64 || h.e_ident[5] != ELFDATA2LSB { return Err(Error::BadClass); }
+        if h.e_ident[6] != EV_CURRENT { return Err(Error::BadVersion); }
+        if h.e_type != ET_EXEC { return Err(Error::BadType); }
+        if h.e_machine != EM_X86_64 { return Err(Error::BadMachine); }
+
+        if h.e_phentsize as usize != mem::size_of::<ProgramHeader>() { return Err(Error::Truncated); }
+
+        let phoff = h.e_phoff as usize;
+        let phnum = h.e_phnum as usize;
+
+        match phnum.checked_mul(mem::size_of::<ProgramHeader>()).and_then(|s| s.checked_add(phoff)) {
+            Some(end) if end <= data.len() => {}
+            _ => return Err(Error::Truncated),
+        }
+
+        if phoff % mem::align_of::<ProgramHeader>() != 0 { return Err(Error::Misaligned); }
+
+        let ph = unsafe {
+            slice::from_raw_parts(data.as_ptr().offset(phoff as isize) as *const ProgramHeader, phnum)
+        };
+
+        if ph.iter().any(|p| p.p_type == PT_INTERP) { return Err(Error::Interpreter); }
+
+        for p in ph.iter().filter(|p| p.p_type == PT_LOAD) {
+            if p.p_filesz > p.p_memsz { return Err(Error::BadSegment); }
+            match p.offset().checked_add(p.file_size()) {
+                Some(end) if end <= data.len() => {}
+                _ => return Err(Error::BadSegment),
+            }
+        }
+
+        Ok(Elf { data, h, ph })
+    }
+
+    pub default fn entry(&self) -> usize {
+        self.h.e_entry as usize
+    }
+
+    pub default fn program_headers(&self) -> &'a [ProgramHeader] {
+        self.ph
+    }
+
+    /// Loadable segments.
+    pub fn segments(&self) -> impl Iterator<Item = &'a ProgramHeader> {
+        self.ph.iter().filter(|p| p.p_type == PT_LOAD)
+    }
+
+    /// File contents of the segment `p`.
+    pub fn segment_data(&self, p : &ProgramHeader) -> &'a [u8] {
+        &self.data[p.offset()..p.offset() + p.file_size()]
+    }
+
+    /// Flags requested for the stack by `PT_GNU_STACK`,
+    /// a non-executable stack is assumed without it.
+    pub fn stack_flags(&self) -> u32 {
+        self.ph.iter()
+            .find(|p| p.p_type == PT_GNU_STACK)
+            .map_or(PF_R | PF_W, |p| p.p_flags)
+    }
+
+    /// Address of the program headers in the loaded image, if a
+    /// loadable segment covers them.
+    pub fn phdr_addr(&self) -> Option<usize> {
+        let off = self.h.e_phoff as usize;
+
+        self.segments()
+            .find(|p| off >= p.offset() && off < p.offset() + p.file_size())
+            .map(|p| p.vaddr() + off - p.offset())
+    }
+
+    pub default fn phnum(&self) -> usize {
+        self.ph.len()
+    }
+}
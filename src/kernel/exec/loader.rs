@@ -0,0 +1,191 @@
+// -*- mode: rust; -*-
+
+use alloc::Vec;
+
+use core::mem;
+
+use kernel::{
+    syscall::check_user,
+    mem::{ self
+         , control::MemoryController
+         , alloc::{ frame::Frame
+                  , heap::align_down }
+         , globals::{ PAGE_SIZE
+                    , USER_STACK_TOP
+                    , USER_STACK_PAGES }
+         , paging::{ page::Page
+                   , table::InactivePTable
+                   , entry::{ EFlags
+                            , NO_EXECUTE } } },
+};
+
+use super::elf::{ Elf
+                , Error
+                , ProgramHeader };
+
+const AT_NULL   : usize = 0;
+const AT_PHDR   : usize = 3;
+const AT_PHENT  : usize = 4;
+const AT_PHNUM  : usize = 5;
+const AT_PAGESZ : usize = 6;
+const AT_ENTRY  : usize = 9;
+
+/// Loaded program ready to be entered with `user::jump_to_user`.
+#[derive(Debug, Clone, Copy)]
+pub struct Image {
+    pub entry : usize,
+    pub stack : usize,
+}
+
+/// Flags of a page shared by two segments: writable or executable 
+/// if any of them is.
+fn merge(a : EFlags, b : EFlags) -> EFlags {
+    ((a | b) - NO_EXECUTE) | (a & b & NO_EXECUTE)
+}
+
+/// Copies the part of the segment `p` which falls into the page `pg`.
+fn fill(buf : &mut [u8], pg : Page, p : &ProgramHeader, data : &[u8]) {
+    let s = if pg.start_addr() > p.vaddr() { pg.start_addr() } else { p.vaddr() };
+    let e = if pg.start_addr() + PAGE_SIZE < p.vaddr() + data.len() { pg.start_addr() + PAGE_SIZE } 
+            else { p.vaddr() + data.len() };
+
+    if s < e {
+        buf[s - pg.start_addr()..e - pg.start_addr()]
+            .copy_from_slice(&data[s - p.vaddr()..e - p.vaddr()]);
+    }
+}
+
+/// Builds the topmost stack page as expected by the System V ABI:
+/// argc, argv, envp and the auxiliary vector followed by the strings.
+/// Returns the page contents and the initial stack pointer.
+fn initial_stack(elf : &Elf, args : &[&str], env : &[&str]) -> Result<(Vec<u8>, usize), Error> {
+    let base = USER_STACK_TOP - PAGE_SIZE;
+
+    let mut page = vec![0u8 ; PAGE_SIZE];
+    let mut cur  = PAGE_SIZE;
+
+    let (argv, envp) = {
+        let mut put_str = |s : &str, page : &mut Vec<u8>| -> Result<usize, Error> {
+            if s.len() + 1 > cur { return Err(Error::ArgsTooLong); }
+            cur -= s.len() + 1;
+            page[cur..cur + s.len()].copy_from_slice(s.as_bytes());
+            Ok(base + cur)
+        };
+
+        let argv = args.iter().map(|s| put_str(s, &mut page)).collect::<Result<Vec<usize>, Error>>()?;
+        let envp = env.iter().map(|s| put_str(s, &mut page)).collect::<Result<Vec<usize>, Error>>()?;
+        (argv, envp)
+    };
+
+    let mut words = Vec::new();
+
+    words.push(argv.len());
+    words.extend(argv.iter().cloned());
+    words.push(0);
+    words.extend(envp.iter().cloned());
+    words.push(0);
+
+    if let Some(phdr) = elf.phdr_addr() {
+        words.extend_from_slice(&[AT_PHDR, phdr]);
+    }
+    words.extend_from_slice(&[ AT_PHENT,  mem::size_of::<ProgramHeader>()
+                             , AT_PHNUM,  elf.phnum()
+                             , AT_PAGESZ, PAGE_SIZE
+                             , AT_ENTRY,  elf.entry()
+                             , AT_NULL,   0 ]);
+
+    let size = words.len() * mem::size_of::<usize>();
+    if size > cur { return Err(Error::ArgsTooLong); }
+
+    let rsp = align_down(cur - size, 16);
+
+    words.iter().enumerate().for_each(|(i, w)| {
+        let o = rsp + i * mem::size_of::<usize>();
+        page[o..o + mem::size_of::<usize>()].copy_from_slice(&unsafe { mem::transmute::<usize, [u8 ; 8]>(*w) });
+    });
+
+    Ok((page, base + rsp))
+}
+
+/// Maps the loadable segments of `elf` and a stack holding `args` 
+/// and `env` into the page table `t`. Returns the entry point 
+/// and the initial stack pointer.
+pub fn load(elf : &Elf, t : &mut InactivePTable, args : &[&str], env : &[&str]) -> Result<Image, Error> {
+    let stack_bottom = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+    let mut n = USER_STACK_PAGES;
+
+    for p in elf.segments() {
+        if p.mem_size() == 0 { continue; }
+        check_user(p.vaddr(), p.mem_size()).map_err(|_| Error::BadSegment)?;
+
+        // `check_user` lets a segment end at the stack top
+        if p.vaddr() + p.mem_size() > stack_bottom { return Err(Error::BadSegment); }
+
+        n += Page::caddr(p.vaddr() + p.mem_size() - 1).i - Page::caddr(p.vaddr()).i + 1;
+    }
+
+    // the heap can only grow into its reserve while the 
+    // controller is held, so allocate what can be up front
+    let (top, rsp) = initial_stack(elf, args, env)?;
+    let mut pages  = Vec::with_capacity(n);
+
+    let mut mc = mem::controller();
+
+    match fill_pages(&mut mc, elf, &top, &mut pages) {
+        Ok(()) => {
+            mc.map_into(t, &pages);
+            Ok(Image { entry : elf.entry(), stack : rsp })
+        }
+        Err(e) => {
+            pages.into_iter().for_each(|(_, fr, _)| mc.free_frame(fr));
+            Err(e)
+        }
+    }
+}
+
+/// Collects the pages of the segments and the stack into `pages`, 
+/// which the caller frees on error.
+fn fill_pages( mc    : &mut MemoryController
+             , elf   : &Elf
+             , top   : &[u8]
+             , pages : &mut Vec<(Page, Frame, EFlags)> ) -> Result<(), Error> 
+{
+    for p in elf.segments() {
+        if p.mem_size() == 0 { continue; }
+
+        let fl   = EFlags::from_elf_program_flags(p.flags());
+        let data = elf.segment_data(p);
+
+        let sp = Page::caddr(p.vaddr());
+        let ep = Page::caddr(p.vaddr() + p.mem_size() - 1);
+
+        for pg in Page::range_inclusive(sp, ep) {
+            if let Some(e) = pages.iter_mut().find(|e| e.0 == pg) {
+                mc.fill_frame(&e.1, |buf| fill(buf, pg, p, data));
+                e.2 = merge(e.2, fl);
+                continue;
+            }
+
+            let fr = mc.alloc_frame_with(|buf| {
+                    for b in buf.iter_mut() { *b = 0; }
+                    fill(buf, pg, p, data);
+                }).ok_or(Error::NoMemory)?;
+
+            pages.push((pg, fr, fl));
+        }
+    }
+
+    let stack_fl = EFlags::from_elf_program_flags(elf.stack_flags());
+
+    for i in 0..USER_STACK_PAGES {
+        let pg = Page::caddr(USER_STACK_TOP - (i + 1) * PAGE_SIZE);
+        let fr = mc.alloc_frame_with(|buf| {
+                if i == 0 { buf.copy_from_slice(top); } 
+                else { for b in buf.iter_mut() { *b = 0; } }
+            }).ok_or(Error::NoMemory)?;
+
+        pages.push((pg, fr, stack_fl));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,8 @@
+// -*- mode: rust; -*-
+
+//! # User program loading
+
+pub mod elf;
+
+mod loader;
+pub use self::loader::{ load, Image };
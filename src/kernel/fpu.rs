@@ -0,0 +1,233 @@
+// -*- mode: rust; -*-
+
+//! # FPU and SIMD state
+//!
+//! User code may use x87, SSE and, when the CPU has XSAVE, AVX. The 
+//! kernel itself is built soft-float, so the registers only ever hold 
+//! user state, or kernel state inside `with_fpu`.
+//!
+//! The state is switched lazily: a thread switch only sets CR0.TS, the
+//! first FPU instruction afterwards raises #NM and `trap` saves the 
+//! registers of the thread which last used them before loading the 
+//! ones of the running thread. Threads which never touch the FPU 
+//! never pay for it.
+
+use alloc::boxed::Box;
+
+use core::sync::atomic::{ AtomicBool
+                        , AtomicUsize
+                        , Ordering };
+
+use kernel::{
+    cpu,
+    smp::percpu,
+};
+
+const CR0_MP  : u64 = 1 << 1;
+const CR0_EM  : u64 = 1 << 2;
+const CR0_TS  : u64 = 1 << 3;
+const CR0_NE  : u64 = 1 << 5;
+
+const CR4_OSFXSR     : u64 = 1 <<  9;
+const CR4_OSXMMEXCPT : u64 = 1 << 10;
+const CR4_OSXSAVE    : u64 = 1 << 18;
+
+/// XCR0 components: x87, SSE and the upper halves of the AVX registers.
+const XCR0_X87 : u64 = 1 << 0;
+const XCR0_SSE : u64 = 1 << 1;
+const XCR0_AVX : u64 = 1 << 2;
+
+/// Size of the `fxsave` area.
+const FXSAVE_SIZE : usize = 512;
+
+/// Save areas are 64 byte aligned for `xsave`.
+const AREA_ALIGN : usize = 64;
+
+/// Values of FCW and MXCSR after `fninit`, all exceptions masked.
+const FCW_INIT   : u16 = 0x037F;
+const MXCSR_INIT : u32 = 0x1F80;
+
+static XSAVE : AtomicBool = AtomicBool::new(false);
+
+/// Size of the save area, set by `init` from CPUID once XCR0 is known.
+static AREA_SIZE : AtomicUsize = AtomicUsize::new(FXSAVE_SIZE);
+
+percpu! {
+    /// Save area of the thread whose state is in the registers, 0 if none.
+    static OWNER : usize = 0;
+}
+
+percpu! {
+    /// Save area of the running thread.
+    static CURRENT : usize = 0;
+}
+
+percpu! {
+    /// Set while `with_fpu` runs, the registers hold no thread's state.
+    static KERNEL_USE : bool = false;
+}
+
+unsafe fn read_cr0() -> u64 {
+    let v : u64;
+    asm!("mov $0, cr0" : "=r"(v) ::: "intel", "volatile");
+    v
+}
+
+unsafe fn write_cr0(v : u64) {
+    asm!("mov cr0, $0" :: "r"(v) : "memory" : "intel", "volatile");
+}
+
+unsafe fn set_ts() {
+    write_cr0(read_cr0() | CR0_TS);
+}
+
+unsafe fn clts() {
+    asm!("clts" :::: "volatile");
+}
+
+unsafe fn save(area : usize) {
+    if XSAVE.load(Ordering::Relaxed) {
+        asm!("xsave64 ($0)" :: "r"(area), "{eax}"(!0u32), "{edx}"(!0u32) : "memory" : "volatile");
+    } else {
+        asm!("fxsave64 ($0)" :: "r"(area) : "memory" : "volatile");
+    }
+}
+
+unsafe fn restore(area : usize) {
+    if XSAVE.load(Ordering::Relaxed) {
+        asm!("xrstor64 ($0)" :: "r"(area), "{eax}"(!0u32), "{edx}"(!0u32) : "memory" : "volatile");
+    } else {
+        asm!("fxrstor64 ($0)" :: "r"(area) : "memory" : "volatile");
+    }
+}
+
+/// Saved registers of a thread.
+pub struct State {
+    buf : Box<[u8]>,
+}
+
+impl State {
+    /// Returns the state of `fninit`.
+    pub fn new() -> State {
+        let mut s = State { buf : vec![0 ; AREA_SIZE.load(Ordering::Relaxed) + AREA_ALIGN].into_boxed_slice() };
+
+        // a zeroed XSAVE header makes `xrstor` init the other components
+        unsafe {
+            *(s.area() as *mut u16)        = FCW_INIT;
+            *((s.area() + 24) as *mut u32) = MXCSR_INIT;
+        }
+        s
+    }
+
+    fn area(&mut self) -> usize {
+        let p = self.buf.as_mut_ptr() as usize;
+        (p + AREA_ALIGN - 1) & !(AREA_ALIGN - 1)
+    }
+}
+
+impl Drop for State {
+    /// Threads die on the processor they run on, so only 
+    /// its registers can still belong to this state.
+    fn drop(&mut self) {
+        let area = self.area();
+        OWNER.with(|o| if *o == area { *o = 0; });
+    }
+}
+
+/// Enables the FPU and SSE, with XSAVE and AVX if the CPU has them, 
+/// on the calling processor. Its first use traps to `trap`.
+pub fn init() {
+    assert!(cpu::has(cpu::FXSR | cpu::SSE | cpu::SSE2), "the CPU has no SSE support");
+
+    unsafe {
+        write_cr0((read_cr0() & !CR0_EM) | CR0_MP | CR0_NE);
+
+        let mut cr4 : u64;
+        asm!("mov $0, cr4" : "=r"(cr4) ::: "intel", "volatile");
+        cr4 |= CR4_OSFXSR | CR4_OSXMMEXCPT;
+        if cpu::has(cpu::XSAVE) { cr4 |= CR4_OSXSAVE; }
+        asm!("mov cr4, $0" :: "r"(cr4) : "memory" : "intel", "volatile");
+
+        if cpu::has(cpu::XSAVE) {
+            let xcr0 = XCR0_X87 | XCR0_SSE | if cpu::has(cpu::AVX) { XCR0_AVX } else { 0 };
+            asm!("xsetbv" :: "{ecx}"(0), "{eax}"(xcr0 as u32), "{edx}"((xcr0 >> 32) as u32) :: "volatile");
+
+            // size of the area for the components enabled in XCR0
+            let (_, size, ..) = cpu::cpuid(0xD, 0);
+            AREA_SIZE.store(size as usize, Ordering::SeqCst);
+            XSAVE.store(true, Ordering::SeqCst);
+        }
+
+        asm!("fninit" :::: "volatile");
+        set_ts();
+    }
+}
+
+/// Makes `s` the state of the running thread, called on thread switches.
+pub fn switch_to(s : &mut State) {
+    assert!(!KERNEL_USE.with(|k| *k), "thread switch inside with_fpu");
+
+    let area = s.area();
+    CURRENT.with(|c| *c = area);
+
+    // the registers stay loaded if the thread was the last to use them
+    unsafe {
+        if OWNER.with(|o| *o) == area { clts(); } else { set_ts(); }
+    }
+}
+
+/// Handles #NM: hands the registers over to the running thread.
+/// Runs with interrupts disabled, from the interrupt gate.
+pub fn trap() {
+    unsafe {
+        clts();
+
+        let cur   = CURRENT.with(|c| *c);
+        let owner = OWNER.with(|o| *o);
+
+        assert!(cur != 0, "FPU used before threads are initialized");
+
+        if owner != cur {
+            if owner != 0 { save(owner); }
+
+            restore(cur);
+            OWNER.with(|o| *o = cur);
+        }
+    }
+}
+
+/// Runs `f` with the FPU and SIMD registers free for kernel use. The 
+/// state they held is saved for its thread first, and `f` runs 
+/// without preemption so nothing else sees the registers meanwhile. 
+/// `f` has to bring its own SIMD code, the kernel is built soft-float.
+///
+/// Nothing saves the registers of `f`: it must not block, yield or 
+/// call `with_fpu` again, which is checked.
+pub fn with_fpu<F, R>(f : F) -> R
+where
+    F : FnOnce() -> R
+{
+    percpu::preempt_disable();
+
+    assert!(!KERNEL_USE.with(|k| ::core::mem::replace(k, true)), "nested with_fpu");
+
+    unsafe {
+        clts();
+
+        let owner = OWNER.with(|o| *o);
+        if owner != 0 { save(owner); }
+        OWNER.with(|o| *o = 0);
+
+        asm!("fninit" :::: "volatile");
+        asm!("ldmxcsr ($0)" :: "r"(&MXCSR_INIT) :: "volatile");
+    }
+
+    let r = f();
+
+    // the next user of the registers reloads its own state
+    unsafe { set_ts() };
+    KERNEL_USE.with(|k| *k = false);
+    percpu::preempt_enable();
+
+    r
+}
@@ -0,0 +1,178 @@
+// -*- mode: rust; -*-
+
+//! # Page fault resolution
+//!
+//! A page fault is offered to the resolvers registered for the range 
+//! containing the faulting address, in registration order. The first one 
+//! which doesn't decline decides whether the faulting instruction is 
+//! retried or the fault is fatal.
+
+use x86_64::structures::idt::PageFaultErrorCode;
+
+use kernel::{
+    thread,
+    sync::SpinLock,
+    mem::{ control
+         , alloc::stack
+         , globals::{ PAGE_SIZE
+                    , HEAP_START
+                    , HEAP_MAX_SIZE
+                    , STACK_START
+                    , STACK_ALLOCATOR_SIZE
+                    , USER_START
+                    , USER_END
+                    , USER_STACK_TOP
+                    , USER_STACK_MAX_PAGES }
+         , paging::entry::{ WRITABLE
+                          , NO_EXECUTE
+                          , USER_ACCESSIBLE } },
+};
+
+/// Capacity of the registry, it can't use the heap as 
+/// the heap itself grows through page faults.
+const MAX_RESOLVERS : usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    pub addr : usize,
+    pub code : PageFaultErrorCode,
+    /// address of the faulting instruction
+    pub ip   : usize,
+}
+
+impl Fault {
+    /// The page was present, i.e. the access violated its flags.
+    pub fn present(&self) -> bool {
+        self.code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+    }
+
+    pub fn write(&self) -> bool {
+        self.code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+    }
+
+    pub fn user(&self) -> bool {
+        self.code.contains(PageFaultErrorCode::USER_MODE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// the page is fixed up, retry the access
+    Resolved,
+    /// not for this resolver, ask the next one
+    Declined,
+    /// the access can't be allowed, for the given reason
+    Fatal(&'static str),
+}
+
+pub type Resolver = fn(&Fault) -> Outcome;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    s    : usize,
+    e    : usize,
+    name : &'static str,
+    f    : Resolver,
+}
+
+static RESOLVERS : SpinLock<[Option<Entry> ; MAX_RESOLVERS]> = SpinLock::new([None ; MAX_RESOLVERS]);
+
+/// Registers `f` for faults in `[s, e)`. Returns `false` if the registry is full.
+pub fn register(s : usize, e : usize, name : &'static str, f : Resolver) -> bool {
+    match RESOLVERS.lock().iter_mut().find(|r| r.is_none()) {
+        Some(r) => { *r = Some(Entry { s, e, name, f }); true }
+        None    => false,
+    }
+}
+
+/// Runs the resolvers registered for the faulting address.
+pub fn resolve(f : &Fault) -> Outcome {
+    // resolvers run without the registry lock, they may fault themselves
+    let rs = *RESOLVERS.lock();
+
+    rs.iter()
+      .filter_map(|r| *r)
+      .filter(|r| r.s <= f.addr && f.addr < r.e)
+      .map(|r| (r.f)(f))
+      .find(|&o| o != Outcome::Declined)
+      .unwrap_or(Outcome::Declined)
+}
+
+/// Prints the fatal fault `f`, naming the running thread if it can be 
+/// told without waiting for the scheduler.
+pub fn report(f : &Fault, why : &str) {
+    match thread::try_current() {
+        Some((id, name)) => println!("\n{} in thread {} ({})", why, id, name),
+        None             => println!("\n{}", why),
+    }
+
+    println!("\taddr: {:#x}, ip: {:#x}, e: [{:?}]", f.addr, f.ip, f.code);
+
+    let r = RESOLVERS.lock();
+    if let Some(r) = r.iter().filter_map(|r| r.as_ref()).find(|r| r.s <= f.addr && f.addr < r.e) {
+        println!("\tin range {:#x} - {:#x} ({})", r.s, r.e, r.name);
+    }
+}
+
+/// Registers the resolvers of the kernel's own regions.
+pub fn init() {
+    let stack_end = STACK_START + (STACK_ALLOCATOR_SIZE + 1) * PAGE_SIZE;
+    let user_stack_end = USER_STACK_TOP - USER_STACK_MAX_PAGES * PAGE_SIZE;
+
+    register(HEAP_START, HEAP_START + HEAP_MAX_SIZE, "heap", heap);
+    register(STACK_START, stack_end, "kernel stacks", kernel_stack);
+    register(user_stack_end, USER_STACK_TOP, "user stack", user_stack);
+    register(USER_START, USER_END, "copy-on-write", copy_on_write);
+    register(USER_START, USER_END, "mmap", mmap);
+}
+
+/// Maps heap pages on demand.
+fn heap(f : &Fault) -> Outcome {
+    if f.present() { return Outcome::Declined; }
+
+    if control::held_here() { return Outcome::Fatal("heap fault under the memory controller"); }
+
+    if control::heap_fault(f.addr) { Outcome::Resolved } 
+    else { Outcome::Fatal("heap page cannot be mapped") }
+}
+
+/// Kernel stacks are mapped up front and never grow.
+fn kernel_stack(f : &Fault) -> Outcome {
+    if f.present() { return Outcome::Declined; }
+
+    match stack::guard_of(f.addr) {
+        Some(_) => Outcome::Fatal("kernel stack overflow: guard page hit"),
+        None    => Outcome::Declined,
+    }
+}
+
+/// Grows user stacks up to `USER_STACK_MAX_PAGES`, for threads of a process.
+fn user_stack(f : &Fault) -> Outcome {
+    if f.present() || thread::current_process().is_none() { return Outcome::Declined; }
+    if control::held_here() { return Outcome::Fatal("user stack fault under the memory controller"); }
+
+    if control::demand_fault(f.addr, USER_ACCESSIBLE | WRITABLE | NO_EXECUTE) { Outcome::Resolved } 
+    else { Outcome::Fatal("user stack cannot grow") }
+}
+
+fn copy_on_write(f : &Fault) -> Outcome {
+    if !(f.present() && f.write()) { return Outcome::Declined; }
+    if control::held_here() { return Outcome::Fatal("copy-on-write fault under the memory controller"); }
+
+    if control::cow_fault(f.addr) { Outcome::Resolved } 
+    else { Outcome::Declined }
+}
+
+/// Backs regions reserved by `mmap` on first touch.
+fn mmap(f : &Fault) -> Outcome {
+    if f.present() { return Outcome::Declined; }
+
+    let fl = thread::current_process().and_then(|p| p.region_flags(f.addr));
+    if fl.is_some() && control::held_here() { return Outcome::Fatal("mmap fault under the memory controller"); }
+
+    match fl {
+        Some(fl) if control::demand_fault(f.addr, fl) => Outcome::Resolved,
+        Some(_) => Outcome::Fatal("mmap page cannot be mapped"),
+        None    => Outcome::Declined,
+    }
+}
@@ -38,18 +38,19 @@ impl GDT {
         panic!("Attempt to overflow GDT");
     }
 
-    /// Add descriptor to an existing GDT 
+    /// Add descriptor to an existing GDT, the requested
+    /// privilege level of the selector matches the descriptor's
     pub fn add_entry(&mut self, e : &Descriptor) -> SegmentSelector {
-        let i = match *e {
-            Descriptor::UserSegment(v) => self.push(v),
+        let (i, rpl) = match *e {
+            Descriptor::UserSegment(v) => (self.push(v), e.privilege_level()),
             Descriptor::SystemSegment(l, h) => {
                 let i = self.push(l);
                 self.push(h);
-                i
+                (i, PrivilegeLevel::Ring0)
             }
         };
 
-        SegmentSelector::new(i as u16, PrivilegeLevel::Ring0)
+        SegmentSelector::new(i as u16, rpl)
     }
 
     /// Load the GDT
@@ -66,9 +67,11 @@ impl GDT {
 /// Descriptor flags definition
 bitflags! {
     flags DescriptorFlags : u64 {
+        const WRITABLE      = 1 << 41,
         const CONFORMING    = 1 << 42,
         const EXECUTABLE    = 1 << 43,
         const USER_SEGMENT  = 1 << 44,
+        const DPL_RING_3    = 3 << 45,
         const PRESENT       = 1 << 47,
         const LONG_MODE     = 1 << 53,
     }
@@ -84,6 +87,19 @@ pub enum Descriptor {
     SystemSegment(u64, u64),
 }
 
+/// Selectors of the segments every CPU loads.
+///
+/// The order of the data and code segments follows what `sysret` 
+/// expects: user data right after kernel data, then user code.
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    pub kernel_code : SegmentSelector,
+    pub kernel_data : SegmentSelector,
+    pub user_data   : SegmentSelector,
+    pub user_code   : SegmentSelector,
+    pub tss         : SegmentSelector,
+}
+
 impl Descriptor {
     /// Returns user code segment with stadnard flags provided.
     pub fn kernel_code_segment() -> Descriptor {
@@ -91,6 +107,33 @@ impl Descriptor {
         Descriptor::UserSegment(flags.bits())
     }
 
+    /// Returns kernel data segment, in long mode only the 
+    /// present and writable bits are taken into account.
+    pub fn kernel_data_segment() -> Descriptor {
+        let flags = USER_SEGMENT | PRESENT | WRITABLE;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    /// Returns ring 3 code segment.
+    pub fn user_code_segment() -> Descriptor {
+        let flags = EXECUTABLE | USER_SEGMENT | PRESENT | LONG_MODE | DPL_RING_3;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    /// Returns ring 3 data segment.
+    pub fn user_data_segment() -> Descriptor {
+        let flags = USER_SEGMENT | PRESENT | WRITABLE | DPL_RING_3;
+        Descriptor::UserSegment(flags.bits())
+    }
+
+    /// Descriptor privilege level of the segment.
+    pub fn privilege_level(&self) -> PrivilegeLevel {
+        match *self {
+            Descriptor::UserSegment(v) if v.get_bits(45..47) == 3 => PrivilegeLevel::Ring3,
+            _ => PrivilegeLevel::Ring0,
+        }
+    }
+
 
     /// Create TSS mode segment.
     ///
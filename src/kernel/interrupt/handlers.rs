@@ -1,31 +1,150 @@
 // -*- mode: rust; -*-
 
 //! # x86 Interrupt handler functions
+//!
+//! Handlers which can be entered from ring 3 start with a 
+//! `percpu::UserEntry` so they run on the kernel GS base.
 
 use x86_64::structures::idt::{ExceptionStackFrame, PageFaultErrorCode};
 
+use kernel::{
+    fpu,
+    thread,
+    mem::alloc::stack,
+    smp::{ tlb
+         , percpu::UserEntry },
+};
+
+use super::{ pic
+           , pit
+           , fault::{ self
+                    , Fault
+                    , Outcome } };
+
 pub extern "x86-interrupt" fn __breakpoint_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
     println!("\nEXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
 pub extern "x86-interrupt" fn __page_fault_handler( stack_frame : &mut ExceptionStackFrame
                                                   , error_code  : PageFaultErrorCode ) {
+    let _gs = UserEntry::new(stack_frame);
+
     use x86_64::registers::control_regs;
-    println!("\nEXCEPTION: PAGE FAULT [{:#x}] ; e: [{:?}]\n{:#?}", control_regs::cr2(), error_code, stack_frame);
-    loop {}
+
+    let f = Fault { 
+        addr : control_regs::cr2().0, 
+        code : error_code, 
+        ip   : stack_frame.instruction_pointer.0,
+    };
+
+    let why = match fault::resolve(&f) {
+        Outcome::Resolved   => return,
+        Outcome::Fatal(why) => why,
+        Outcome::Declined   => "EXCEPTION: PAGE FAULT",
+    };
+
+    fault::report(&f, why);
+    println!("{:#?}", stack_frame);
+
+    // a faulting user thread is killed, the kernel can't go on
+    if f.user() {
+        thread::exit();
+    }
+
+    panic!("unresolved page fault in the kernel");
 }
 
+/// An overflowing kernel stack ends up here: the page fault caused by 
+/// the guard page can't push its frame on the same stack.
 pub extern "x86-interrupt" fn __double_fault_handler(stack_frame : &mut ExceptionStackFrame, ec : u64) {
-    println!("\nEXCEPTION: DOUBLE FAULT [e = {}]\n{:#?}", ec, stack_frame);
+    use x86_64::registers::control_regs;
+
+    let guard = stack::guard_of(control_regs::cr2().0)
+        .or_else(|| stack::guard_of(stack_frame.stack_pointer.0));
+
+    match (guard, thread::try_current()) {
+        (Some(g), Some((id, name))) => 
+            println!("\nkernel stack overflow in thread {} ({}): guard page {:#x} below stack {:#x} - {:#x}", 
+                     id, name, g.page, g.bottom, g.top),
+        (Some(g), None) => 
+            println!("\nkernel stack overflow: guard page {:#x} below stack {:#x} - {:#x}", g.page, g.bottom, g.top),
+        (None, _) => 
+            println!("\nEXCEPTION: DOUBLE FAULT [e = {}]", ec),
+    }
+
+    println!("{:#?}", stack_frame);
+    loop {}
+}
+
+pub extern "x86-interrupt" fn __nmi_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::paranoid();
+    println!("\nEXCEPTION: NON-MASKABLE INTERRUPT\n{:#?}", stack_frame);
+}
+
+pub extern "x86-interrupt" fn __machine_check_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::paranoid();
+    println!("\nEXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
     loop {}
 }
 
 pub extern "x86-interrupt" fn __invalid_opcode_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
     println!("\nEXCEPTION: INVALID OPCODE [{:#x}]\n{:#?}", stack_frame.instruction_pointer, stack_frame);
     loop {}
 }
 
 pub extern "x86-interrupt" fn __divide_by_zero_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
     println!("\nEXCEPTION: DIVIDE BY ZERO\n{:#?}", stack_frame);
     loop {}
 }
+
+/// First FPU or SIMD instruction since the last thread switch.
+pub extern "x86-interrupt" fn __device_not_available_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
+    fpu::trap();
+}
+
+/// Unmasked x87 exception, reported on the next FPU instruction.
+pub extern "x86-interrupt" fn __x87_floating_point_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
+    fp_exception("x87 FLOATING POINT", stack_frame);
+}
+
+/// Unmasked SSE exception.
+pub extern "x86-interrupt" fn __simd_floating_point_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
+    fp_exception("SIMD FLOATING POINT", stack_frame);
+}
+
+/// A user thread raising a floating point exception is killed, 
+/// the kernel is built soft-float and never should.
+fn fp_exception(what : &str, stack_frame : &ExceptionStackFrame) -> ! {
+    println!("\nEXCEPTION: {}\n{:#?}", what, stack_frame);
+
+    if stack_frame.code_segment & 3 == 3 {
+        thread::exit();
+    }
+
+    panic!("floating point exception in the kernel");
+}
+
+/// Spurious interrupts of the local APIC don't take an EOI.
+pub extern "x86-interrupt" fn __spurious_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
+}
+
+/// Another processor changed mappings this one may cache.
+pub extern "x86-interrupt" fn __tlb_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
+    tlb::handle();
+}
+
+pub extern "x86-interrupt" fn __timer_handler(stack_frame : &mut ExceptionStackFrame) {
+    let _gs = UserEntry::new(stack_frame);
+
+    let now = pit::tick();
+    pic::eoi(pic::TIMER_IRQ);
+    thread::tick(now);
+}
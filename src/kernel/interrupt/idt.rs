@@ -5,23 +5,42 @@ use spin::Once;
 use x86_64::{
     VirtualAddress,
     structures::{ idt::Idt
-                , gdt::SegmentSelector
                 , tss::TaskStateSegment },
-    instructions::{ segmentation::set_cs
+    instructions::{ segmentation::{ set_cs
+                                  , load_ss
+                                  , load_ds
+                                  , load_es }
                   , tables::load_tss },
 };
 
 use super::{
     gdt,
+    pic,
+    fault,
+    pit,
     handlers::*,
 };
 
-use kernel::mem::control::MemoryController;
+use kernel::{
+    mem::control::MemoryController,
+    smp::{ apic
+         , percpu
+         , tlb
+         , MAX_CPUS },
+};
+
+pub const DOUBLE_FAULT_IST_IDX  : usize = 0;
+pub const NMI_IST_IDX           : usize = 1;
+pub const MACHINE_CHECK_IST_IDX : usize = 2;
+
+/// Size of the stacks of the exceptions which can't trust the current one.
+const IST_STACK_PAGES : usize = 2;
 
-pub const DOUBLE_FAULT_IST_IDX : usize = 0;
+/// Size of the per-CPU scratch stack.
+const SCRATCH_STACK_PAGES : usize = 2;
 
-static TSS : Once<TaskStateSegment> = Once::new();
-static GDT : Once<gdt::GDT> = Once::new();
+/// Same on every CPU as all GDTs have the same layout.
+static SELECTORS : Once<gdt::Selectors> = Once::new();
 
 /// Enable hardware interrupts
 pub unsafe fn enable() {
@@ -33,6 +52,37 @@ pub unsafe fn disable() {
     asm!("cli");
 }
 
+/// Returns `true` if hardware interrupts are enabled
+pub fn enabled() -> bool {
+    use x86_64::registers::flags::{ flags, Flags };
+    flags().contains(Flags::IF)
+}
+
+/// Disable hardware interrupts and return whether they were enabled
+pub fn save_disable() -> bool {
+    let e = enabled();
+    unsafe { disable() };
+    e
+}
+
+/// Re-enable hardware interrupts if `save_disable` found them enabled
+pub fn restore(e : bool) {
+    if e { unsafe { enable() }; }
+}
+
+/// Returns the segment selectors loaded by `init`
+pub fn selectors() -> gdt::Selectors {
+    *SELECTORS.try().expect("GDT is not initialized")
+}
+
+/// Sets the stack the calling CPU switches to when an 
+/// interrupt or a system call comes from ring 3
+pub fn set_kernel_stack(top : usize) {
+    // the TSS is only read by the CPU on privilege changes,
+    // which can't happen while the kernel is updating it
+    percpu::set_kernel_stack(top);
+}
+
 lazy_static! {
     static ref IDT : Idt = {
         let mut idt = Idt::new();
@@ -41,42 +91,86 @@ lazy_static! {
         idt.divide_by_zero.set_handler_fn(__divide_by_zero_handler);
         idt.invalid_opcode.set_handler_fn(__invalid_opcode_handler);
         idt.page_fault.set_handler_fn(__page_fault_handler);
+        idt.device_not_available.set_handler_fn(__device_not_available_handler);
+        idt.x87_floating_point.set_handler_fn(__x87_floating_point_handler);
+        idt.simd_floating_point.set_handler_fn(__simd_floating_point_handler);
+
+        idt.interrupts[(pic::vector(pic::TIMER_IRQ) - pic::PIC1_OFFSET) as usize]
+           .set_handler_fn(__timer_handler);
+        idt.interrupts[(apic::SPURIOUS_VECTOR - pic::PIC1_OFFSET) as usize]
+           .set_handler_fn(__spurious_handler);
+        idt.interrupts[(tlb::TLB_VECTOR - pic::PIC1_OFFSET) as usize]
+           .set_handler_fn(__tlb_handler);
 
         unsafe {
             idt.double_fault.set_handler_fn(__double_fault_handler)
                             .set_stack_index(DOUBLE_FAULT_IST_IDX as u16);
+            idt.non_maskable_interrupt.set_handler_fn(__nmi_handler)
+                                      .set_stack_index(NMI_IST_IDX as u16);
+            idt.machine_check.set_handler_fn(__machine_check_handler)
+                             .set_stack_index(MACHINE_CHECK_IST_IDX as u16);
         }
 
         idt
     };
 }
 
+/// Sets up the bootstrap processor: descriptor tables, 
+/// interrupt controller and timer.
 pub fn init(mc : &mut MemoryController) {
-    let double_fault_stack = mc.alloc(1).expect("double fault stack cannot be allocated");
+    fault::init();
+
+    init_cpu(0, mc);
 
-    let tss = TSS.call_once(|| {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_IDX] = VirtualAddress(double_fault_stack.top());
-        tss
-    });
+    unsafe {
+        pic::init();
+        pit::init();
+    }
+}
 
-    let mut code_selector = SegmentSelector(0);
-    let mut tss_selector  = SegmentSelector(0);
+/// Loads the descriptor tables of the application processor `cpu`.
+pub fn init_ap(cpu : usize, mc : &mut MemoryController) {
+    assert!(cpu > 0 && cpu < MAX_CPUS, "invalid CPU number {}", cpu);
+    init_cpu(cpu, mc);
+}
 
-    let gdt = GDT.call_once(|| {
-        let mut gdt = gdt::GDT::new();
-        
-        code_selector = gdt.add_entry(&gdt::Descriptor::kernel_code_segment());
-        tss_selector  = gdt.add_entry(&gdt::Descriptor::tss_segment(tss));
+/// Creates the per-CPU block of `cpu` with its GDT, TSS and own 
+/// exception stacks, loads them and the shared IDT.
+fn init_cpu(cpu : usize, mc : &mut MemoryController) {
+    let double_fault_stack  = mc.alloc(IST_STACK_PAGES).expect("double fault stack cannot be allocated");
+    let nmi_stack           = mc.alloc(IST_STACK_PAGES).expect("NMI stack cannot be allocated");
+    let machine_check_stack = mc.alloc(IST_STACK_PAGES).expect("machine check stack cannot be allocated");
+    let scratch_stack       = mc.alloc(SCRATCH_STACK_PAGES).expect("scratch stack cannot be allocated");
+
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_IDX]  = VirtualAddress(double_fault_stack.top());
+    tss.interrupt_stack_table[NMI_IST_IDX]           = VirtualAddress(nmi_stack.top());
+    tss.interrupt_stack_table[MACHINE_CHECK_IST_IDX] = VirtualAddress(machine_check_stack.top());
+    tss.privilege_stack_table[0]                     = VirtualAddress(scratch_stack.top());
+
+    percpu::init(cpu, scratch_stack.top(), tss);
+
+    let gdt = &mut percpu::current().gdt;
+    let tss = &percpu::current().tss;
+
+    let selectors = gdt::Selectors {
+        kernel_code : gdt.add_entry(&gdt::Descriptor::kernel_code_segment()),
+        kernel_data : gdt.add_entry(&gdt::Descriptor::kernel_data_segment()),
+        user_data   : gdt.add_entry(&gdt::Descriptor::user_data_segment()),
+        user_code   : gdt.add_entry(&gdt::Descriptor::user_code_segment()),
+        tss         : gdt.add_entry(&gdt::Descriptor::tss_segment(tss)),
+    };
 
-        gdt
-    });
+    let selectors = SELECTORS.call_once(|| selectors);
 
     gdt.load();
 
     unsafe {
-        set_cs(code_selector);
-        load_tss(tss_selector);
+        set_cs(selectors.kernel_code);
+        load_ss(selectors.kernel_data);
+        load_ds(selectors.kernel_data);
+        load_es(selectors.kernel_data);
+        load_tss(selectors.tss);
     }
 
     IDT.load();
@@ -1,8 +1,22 @@
 // -*- mode: rust; -*-
 
 mod idt;
-pub (crate) use self::idt::init;
+pub (crate) use self::idt::{ init
+                           , init_ap
+                           , enable
+                           , disable
+                           , enabled
+                           , save_disable
+                           , restore
+                           , selectors
+                           , set_kernel_stack };
 
 mod handlers;
 
-mod gdt;
+pub mod fault;
+
+pub mod gdt;
+
+mod pic;
+
+pub mod pit;
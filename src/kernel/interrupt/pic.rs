@@ -0,0 +1,55 @@
+// -*- mode: rust; -*-
+
+//! # Chained 8259 programmable interrupt controllers
+
+use x86_64::instructions::port::outb;
+
+const PIC1_CMD  : u16 = 0x20;
+const PIC1_DATA : u16 = 0x21;
+const PIC2_CMD  : u16 = 0xA0;
+const PIC2_DATA : u16 = 0xA1;
+
+const ICW1_INIT : u8 = 0x11;
+const ICW4_8086 : u8 = 0x01;
+const EOI       : u8 = 0x20;
+
+/// First vector of the master PIC, placed right after the CPU exceptions.
+pub const PIC1_OFFSET : u8 = 32;
+/// First vector of the slave PIC.
+pub const PIC2_OFFSET : u8 = PIC1_OFFSET + 8;
+
+pub const TIMER_IRQ   : u8 = 0;
+pub const CASCADE_IRQ : u8 = 2;
+
+/// Remaps both PICs above the exception vectors and masks 
+/// every line except the timer and the cascade.
+pub unsafe fn init() {
+    outb(PIC1_CMD, ICW1_INIT);
+    outb(PIC2_CMD, ICW1_INIT);
+
+    outb(PIC1_DATA, PIC1_OFFSET);
+    outb(PIC2_DATA, PIC2_OFFSET);
+
+    // slave is wired to the IRQ2 line of the master
+    outb(PIC1_DATA, 1 << CASCADE_IRQ);
+    outb(PIC2_DATA, CASCADE_IRQ);
+
+    outb(PIC1_DATA, ICW4_8086);
+    outb(PIC2_DATA, ICW4_8086);
+
+    outb(PIC1_DATA, !((1 << TIMER_IRQ) | (1 << CASCADE_IRQ)));
+    outb(PIC2_DATA, 0xFF);
+}
+
+/// Returns the interrupt vector of the given IRQ line.
+pub fn vector(irq : u8) -> u8 {
+    if irq < 8 { PIC1_OFFSET + irq } else { PIC2_OFFSET + irq - 8 }
+}
+
+/// Acknowledges the given IRQ line.
+pub fn eoi(irq : u8) {
+    unsafe {
+        if irq >= 8 { outb(PIC2_CMD, EOI); }
+        outb(PIC1_CMD, EOI);
+    }
+}
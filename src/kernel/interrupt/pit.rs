@@ -0,0 +1,48 @@
+// -*- mode: rust; -*-
+
+//! # Programmable interval timer, drives the scheduler tick
+
+use x86_64::instructions::port::outb;
+
+use core::sync::atomic::{ AtomicUsize
+                        , ATOMIC_USIZE_INIT
+                        , Ordering };
+
+const PIT_FREQUENCY : usize = 1_193_182;
+
+const PIT_CH0 : u16 = 0x40;
+const PIT_CMD : u16 = 0x43;
+
+/// channel 0, lobyte/hibyte access, rate generator
+const PIT_MODE : u8 = 0x34;
+
+/// Timer interrupts per second.
+pub const HZ : usize = 100;
+
+static TICKS : AtomicUsize = ATOMIC_USIZE_INIT;
+
+pub unsafe fn init() {
+    let div = PIT_FREQUENCY / HZ;
+
+    outb(PIT_CMD, PIT_MODE);
+    outb(PIT_CH0, (div & 0xFF) as u8);
+    outb(PIT_CH0, (div >> 8) as u8);
+}
+
+/// Counts a timer interrupt, returns the new tick count.
+pub fn tick() -> usize {
+    TICKS.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+/// Number of timer interrupts since boot.
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::SeqCst)
+}
+
+pub fn ms_to_ticks(ms : usize) -> usize {
+    (ms * HZ + 999) / 1000
+}
+
+pub fn ticks_to_ms(t : usize) -> usize {
+    t * 1000 / HZ
+}
@@ -15,6 +15,20 @@ macro_rules! print {
 
 
 
+/// Declares a static with one value per processor, reached through
+/// `with` on the calling processor. The initializer is repeated for
+/// every one of the `smp::MAX_CPUS` slots.
+macro_rules! percpu {
+    ($(#[$attr:meta])* static $name:ident : $t:ty = $init:expr ;) => {
+        $(#[$attr])*
+        static $name : $crate::kernel::smp::percpu::PerCpu<$t> = 
+            $crate::kernel::smp::percpu::PerCpu::new([ $init, $init, $init, $init
+                                                     , $init, $init, $init, $init ]);
+    };
+}
+
+
+
 macro_rules! once {
     ($($arg:tt)+) => {{
         fn __once__() {
@@ -0,0 +1,189 @@
+// -*- mode: rust; -*-
+
+//! # Heap debugging allocator
+//!
+//! Wraps `HeapAllocator` so that every block is laid out as
+//!
+//! | header | front red zone | user data | back red zone |
+//!
+//! Red zones are filled with `REDZONE_BYTE` and checked on free, freed
+//! blocks are poisoned with `POISON_BYTE`, and live blocks are recorded
+//! in a fixed table (the heap can't be used for that) together with the
+//! return address of the code that requested them.
+
+use alloc::heap::{ Alloc
+                 , AllocErr
+                 , Layout };
+
+use core::{ mem, ptr, ops::Deref };
+
+use kernel::sync::SpinLock;
+
+use super::heap::{ Heap
+                 , HeapAllocator
+                 , align_up };
+
+const REDZONE      : usize = 16;
+const REDZONE_BYTE : u8    = 0xFD;
+const POISON_BYTE  : u8    = 0x6B;
+const HEADER_MAGIC : usize = 0xA110_CA7E_D0_B10C;
+
+/// Number of frames to skip to reach the caller of the allocator
+/// (the allocator method itself and the `__rust_alloc` shim).
+const CALLER_DEPTH : usize = 2;
+
+const MAX_TRACKED : usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Header {
+    magic  : usize,
+    size   : usize,
+    caller : usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    ptr    : usize,
+    size   : usize,
+    caller : usize,
+}
+
+struct Live {
+    r : [Option<Record> ; MAX_TRACKED],
+    /// allocations which didn't fit into the table
+    untracked : usize,
+}
+
+impl Live {
+    fn insert(&mut self, rec : Record) {
+        match self.r.iter_mut().find(|r| r.is_none()) {
+            Some(r) => *r = Some(rec),
+            None    => self.untracked += 1,
+        }
+    }
+
+    fn remove(&mut self, ptr : usize) {
+        match self.r.iter_mut().find(|r| r.map_or(false, |r| r.ptr == ptr)) {
+            Some(r) => *r = None,
+            None    => self.untracked = self.untracked.saturating_sub(1),
+        }
+    }
+}
+
+static LIVE : SpinLock<Live> = SpinLock::new(Live { r : [None ; MAX_TRACKED], untracked : 0 });
+
+pub struct DebugAllocator(HeapAllocator);
+
+impl DebugAllocator {
+    pub const fn blank() -> DebugAllocator {
+        DebugAllocator(HeapAllocator::blank())
+    }
+}
+
+impl Deref for DebugAllocator {
+    type Target = SpinLock<Heap>;
+
+    fn deref(&self) -> &SpinLock<Heap> {
+        &self.0
+    }
+}
+
+/// Offset of the user data from the start of the block.
+fn front(l : &Layout) -> usize {
+    align_up(mem::size_of::<Header>() + REDZONE, l.align())
+}
+
+/// Layout of the whole block backing `l`.
+fn outer(l : &Layout) -> Layout {
+    let align = if l.align() > mem::align_of::<Header>() { l.align() } else { mem::align_of::<Header>() };
+    Layout::from_size_align(front(l) + l.size() + REDZONE, align).unwrap()
+}
+
+/// Return address `CALLER_DEPTH` frames up the stack.
+/// Relies on the kernel being built with frame pointers.
+#[inline(always)]
+fn caller() -> usize {
+    let mut rbp : usize;
+    unsafe { asm!("mov $0, rbp" : "=r"(rbp) ::: "intel") };
+
+    for _ in 0..CALLER_DEPTH {
+        if rbp == 0 { return 0; }
+        rbp = unsafe { *(rbp as *const usize) };
+    }
+
+    if rbp == 0 { 0 } else { unsafe { *((rbp + 8) as *const usize) } }
+}
+
+/// Checks that both red zones of the block at `user` are intact.
+unsafe fn redzones_intact(user : usize, size : usize) -> bool {
+    let zone = |a : usize| (0..REDZONE).all(|i| *((a + i) as *const u8) == REDZONE_BYTE);
+    zone(user - REDZONE) && zone(user + size)
+}
+
+unsafe impl<'a> Alloc for &'a DebugAllocator {
+    unsafe fn alloc(&mut self, l : Layout) -> Result<*mut u8, AllocErr> {
+        let c = caller();
+        let b = (&self.0).alloc(outer(&l))? as usize;
+        let user = b + front(&l);
+
+        *(b as *mut Header) = Header { magic : HEADER_MAGIC, size : l.size(), caller : c };
+
+        ptr::write_bytes((user - REDZONE) as *mut u8, REDZONE_BYTE, REDZONE);
+        ptr::write_bytes((user + l.size()) as *mut u8, REDZONE_BYTE, REDZONE);
+
+        LIVE.lock().insert(Record { ptr : user, size : l.size(), caller : c });
+
+        Ok(user as *mut u8)
+    }
+
+    unsafe fn dealloc(&mut self, ptr : *mut u8, l : Layout) {
+        let user = ptr as usize;
+        let b = user - front(&l);
+        let h = *(b as *const Header);
+
+        if h.magic != HEADER_MAGIC {
+            panic!("heap: corrupted header of {:#x} freed from {:#x}", user, caller());
+        }
+
+        if h.size != l.size() {
+            panic!("heap: {:#x} allocated from {:#x} with size {:#x} but freed with size {:#x}",
+                   user, h.caller, h.size, l.size());
+        }
+
+        if !redzones_intact(user, h.size) {
+            panic!("heap: red zone of {:#x} ({:#x} bytes, allocated from {:#x}) is overwritten",
+                   user, h.size, h.caller);
+        }
+
+        LIVE.lock().remove(user);
+
+        ptr::write_bytes(b as *mut u8, POISON_BYTE, outer(&l).size());
+
+        (&self.0).dealloc(b as *mut u8, outer(&l))
+    }
+}
+
+/// Validates the red zones of every tracked allocation,
+/// returns the number of corrupted ones.
+pub fn check_all() -> usize {
+    LIVE.lock().r.iter().filter_map(|r| *r).filter(|r| {
+        let ok = unsafe { redzones_intact(r.ptr, r.size) };
+        if !ok { println!("heap: red zone of {:#x} (allocated from {:#x}) is overwritten", r.ptr, r.caller); }
+        !ok
+    }).count()
+}
+
+/// Prints every live allocation for leak hunting.
+pub fn dump() {
+    let live = LIVE.lock();
+
+    println!("live allocations:");
+    live.r.iter().filter_map(|r| *r).for_each(|r| {
+        println!("\t{:#x} {{{:#x}}} from {:#x}", r.ptr, r.size, r.caller);
+    });
+
+    if live.untracked > 0 {
+        println!("\t... and {} untracked", live.untracked);
+    }
+}
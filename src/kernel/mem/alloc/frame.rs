@@ -2,7 +2,103 @@
 
 use kernel::boot::{MemAreaIter, MemArea};
 
-use kernel::mem::globals::{PAGE_SIZE, PhysicalAddress};
+use kernel::mem::globals::{PAGE_SIZE, LOW_MEMORY_END, PhysicalAddress};
+
+use kernel::sync::SpinLock;
+
+/// Capacity of the free frame stack.
+const FREE_FRAMES : usize = 8192;
+
+/// Frames given back by `AreaAllocator::dealloc`, reused before new ones
+/// are taken from the memory areas. The frame allocator can't rely on 
+/// the heap, so this is a fixed stack living in a static.
+struct FreeFrames {
+    f : [usize ; FREE_FRAMES],
+    n : usize,
+    /// frames dropped because the stack was full
+    leaked : usize,
+}
+
+static FREE : SpinLock<FreeFrames> = SpinLock::new(FreeFrames { f : [0 ; FREE_FRAMES], n : 0, leaked : 0 });
+
+/// Capacity of the shared frame table.
+const SHARED_FRAMES : usize = 16384;
+
+const SLOT_EMPTY   : usize = !0;
+const SLOT_DELETED : usize = !0 - 1;
+
+/// Owner counts of frames mapped by more than one address space, e.g.
+/// after a copy-on-write fork. Frames missing from the table have a 
+/// single owner. Open addressing with linear probing in a static, 
+/// for the same reason as `FreeFrames`.
+struct SharedFrames {
+    /// frame numbers
+    k : [usize ; SHARED_FRAMES],
+    /// number of owners
+    c : [usize ; SHARED_FRAMES],
+}
+
+static SHARED : SpinLock<SharedFrames> = SpinLock::new(SharedFrames { 
+    k : [SLOT_EMPTY ; SHARED_FRAMES], 
+    c : [0 ; SHARED_FRAMES],
+});
+
+impl SharedFrames {
+    /// Slot holding `i`, or the first one it can be inserted at.
+    fn slot(&self, i : usize) -> Option<usize> {
+        let h = i.wrapping_mul(0x9E37_79B9_7F4A_7C15) % SHARED_FRAMES;
+        let mut free = None;
+
+        for n in 0..SHARED_FRAMES {
+            let s = (h + n) % SHARED_FRAMES;
+            match self.k[s] {
+                k if k == i          => return Some(s),
+                SLOT_DELETED         => if free.is_none() { free = Some(s) },
+                SLOT_EMPTY           => return free.or(Some(s)),
+                _                    => {}
+            }
+        }
+        free
+    }
+}
+
+/// Adds an owner to `fr`. Fails if the shared frame table is full.
+pub fn share(fr : &Frame) -> Result<(), ()> {
+    let mut t = SHARED.lock();
+    let s = t.slot(fr.i).ok_or(())?;
+
+    if t.k[s] == fr.i { 
+        t.c[s] += 1; 
+    } else { 
+        t.k[s] = fr.i; 
+        t.c[s] = 2; 
+    }
+    Ok(())
+}
+
+/// Drops an owner of `fr`, returns `true` if it was the last one.
+pub fn release(fr : &Frame) -> bool {
+    let mut t = SHARED.lock();
+
+    match t.slot(fr.i) {
+        Some(s) if t.k[s] == fr.i => {
+            t.c[s] -= 1;
+            if t.c[s] == 1 { t.k[s] = SLOT_DELETED; }
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Number of address spaces `fr` is mapped in.
+pub fn owners(fr : &Frame) -> usize {
+    let t = SHARED.lock();
+
+    match t.slot(fr.i) {
+        Some(s) if t.k[s] == fr.i => t.c[s],
+        _ => 1,
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Frame {
@@ -63,6 +159,14 @@ pub struct AreaAllocator {
 
 impl FrameAllocator for AreaAllocator {
     default fn alloc(&mut self) -> Option<Frame> {
+        {
+            let mut free = FREE.lock();
+            if free.n > 0 {
+                free.n -= 1;
+                return Some(Frame { i : free.f[free.n] });
+            }
+        }
+
         if let Some(a) = self.curr {
             let fr = Frame{ i: self.next.i };
 
@@ -79,8 +183,19 @@ impl FrameAllocator for AreaAllocator {
         None
     }
 
-    default fn dealloc(&mut self, _frame: Frame) {
-        unimplemented!()
+    /// Frames shared by several owners only lose one of them.
+    default fn dealloc(&mut self, fr : Frame) {
+        if !release(&fr) { return; }
+
+        let mut free = FREE.lock();
+
+        if free.n < FREE_FRAMES {
+            let n = free.n;
+            free.f[n] = fr.i;
+            free.n += 1;
+        } else {
+            free.leaked += 1;
+        }
     }
 }
 
@@ -92,7 +207,7 @@ impl AreaAllocator {
         let mut a = AreaAllocator {
             area     : mem_area,
             curr     : None,
-            next     : Frame::caddr(0),
+            next     : Frame::caddr(LOW_MEMORY_END),
             kn_start : Frame::caddr(kn_start),
             kn_end   : Frame::caddr(kn_end),
             mb_start : Frame::caddr(mb_start),
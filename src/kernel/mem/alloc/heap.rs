@@ -1,20 +1,38 @@
 // -*- mode: rust; -*-
 
-use spin::Mutex;
-
 use alloc::heap::{ Alloc
                  , AllocErr
+                 , CannotReallocInPlace
                  , Layout };
 
-use core::{ mem, ops::Deref };
+use core::{ mem, ptr, ops::Deref };
+
+use super::{
+    hole::{ Hole
+          , Holes
+          , Strategy
+          , DEFAULT_STRATEGY },
+    slab::{ Slabs
+          , Stats },
+};
 
-use super::hole::{ Hole
-                 , Holes };
+use kernel::{
+    sync::SpinLock,
+    mem::{ control
+         , globals::PAGE_SIZE },
+};
 
 pub struct Heap {
     b : usize,
     s : usize,
     h : Holes,
+    c : Slabs,
+    /// strategy used to pick holes
+    st : Strategy,
+    /// live allocations served by the holes list
+    n : usize,
+    /// bytes of those allocations
+    l : usize,
 }
 
 impl Heap {
@@ -22,8 +40,12 @@ impl Heap {
     pub default const fn blank() -> Heap {
         Heap {
             h : Holes::blank(),
+            c : Slabs::blank(),
+            st : DEFAULT_STRATEGY,
             b : 0,
             s : 0,
+            n : 0,
+            l : 0,
         }
     }
 
@@ -35,7 +57,7 @@ impl Heap {
     }
 
     pub unsafe fn new(b : usize, s : usize) -> Heap {
-        Heap { h : Holes::new(b, s), b, s }
+        Heap { h : Holes::new(b, s), c : Slabs::blank(), st : DEFAULT_STRATEGY, b, s, n : 0, l : 0 }
     }
 
     pub default fn bottom(&self) -> usize {
@@ -50,36 +72,180 @@ impl Heap {
         self.b + self.s
     }
 
+    pub default fn strategy(&self) -> Strategy {
+        self.st
+    }
+
+    /// Changes the strategy used for large allocations.
+    pub fn set_strategy(&mut self, st : Strategy) {
+        self.st = st;
+    }
+
+    /// Grows the heap so that an allocation of `l` would fit
+    /// at the top, mapping the new pages beforehand.
+    pub fn grow(&mut self, l : &Layout) -> Result<(), AllocErr> {
+        let n = align_up(l.size() + l.align() + Holes::min_size(), PAGE_SIZE);
+
+        control::map_heap(self.top(), n)
+            .map_err(|_| AllocErr::Exhausted { request : l.clone() })?;
+
+        unsafe { self.extend(n) };
+        Ok(())
+    }
+
     pub unsafe fn extend(&mut self, n : usize) {
         let top = self.top();
         self.h.dealloc(top as *mut u8, &Layout::from_size_align(n, 1).unwrap());
         self.s += n;
     }
 
-    pub fn alloc_first_fit(&mut self, l : &Layout) -> Result<*mut u8, AllocErr> {
-        let s = if l.size() < Holes::min_size() { Holes::min_size() } else { l.size() };
-        let s = align_up(s, mem::align_of::<Hole>());
-        let l = Layout::from_size_align(s, l.align()).unwrap();
+    /// Allocates `l` from the matching slab cache, or from the 
+    /// holes list for large and strictly aligned layouts.
+    pub fn allocate(&mut self, l : &Layout) -> Result<*mut u8, AllocErr> {
+        match Slabs::class(l) {
+            Some(i) => self.alloc_slab(i),
+            None    => {
+                let p = self.alloc_or_grow(l)?;
+                self.n += 1;
+                self.l += hole_layout(l).size();
+                Ok(p)
+            }
+        }
+    }
+
+    /// Frees an allocation obtained by `allocate`.
+    pub unsafe fn deallocate(&mut self, ptr : *mut u8, l : &Layout) {
+        match Slabs::class(l) {
+            Some(i) => self.c.get_mut(i).dealloc(ptr),
+            None    => {
+                self.n -= 1;
+                self.l -= hole_layout(l).size();
+                self.dealloc(ptr, l)
+            }
+        }
+    }
+
+    /// Grows the allocation at `ptr` from `l` to `nl` without moving it.
+    /// Blocks of the same size class already fit, large blocks take
+    /// the adjacent hole if it's big enough.
+    pub unsafe fn grow_in_place(&mut self, ptr : *mut u8, l : &Layout, nl : &Layout) -> bool {
+        match (Slabs::class(l), Slabs::class(nl)) {
+            (Some(i), Some(j)) => i == j,
+            (None, None) => {
+                let (s, ns) = (hole_layout(l).size(), hole_layout(nl).size());
+                let grown = self.h.grow_in_place(ptr as usize, s, ns);
+                if grown { self.l += ns - s; }
+                grown
+            }
+            _ => false,
+        }
+    }
+
+    /// Shrinks the allocation at `ptr` from `l` to `nl` without moving it.
+    pub unsafe fn shrink_in_place(&mut self, ptr : *mut u8, l : &Layout, nl : &Layout) -> bool {
+        match (Slabs::class(l), Slabs::class(nl)) {
+            (Some(i), Some(j)) => i == j,
+            (None, None) => {
+                let (s, ns) = (hole_layout(l).size(), hole_layout(nl).size());
+                let shrunk = self.h.shrink_in_place(ptr as usize, s, ns);
+                if shrunk { self.l -= s - ns; }
+                shrunk
+            }
+            _ => false,
+        }
+    }
+
+    /// Resizes the allocation at `ptr` in place when possible,
+    /// otherwise moves it to a fresh block.
+    pub unsafe fn reallocate(&mut self, ptr : *mut u8, l : &Layout, nl : &Layout) -> Result<*mut u8, AllocErr> {
+        if l.align() == nl.align() {
+            let in_place = if nl.size() >= l.size() { self.grow_in_place(ptr, l, nl) } 
+                           else { self.shrink_in_place(ptr, l, nl) };
+
+            if in_place { return Ok(ptr); }
+        }
+
+        let p = self.allocate(nl)?;
+        ptr::copy_nonoverlapping(ptr, p, if l.size() < nl.size() { l.size() } else { nl.size() });
+        self.deallocate(ptr, l);
+        Ok(p)
+    }
 
-        self.h.alloc_first_fit(l)
+    fn alloc_slab(&mut self, i : usize) -> Result<*mut u8, AllocErr> {
+        if let Some(p) = self.c.get_mut(i).alloc() { return Ok(p); }
+
+        let l = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap();
+        let b = self.alloc_or_grow(&l)?;
+
+        let slab = self.c.get_mut(i);
+        unsafe { slab.refill(b as usize, PAGE_SIZE) };
+        Ok(slab.alloc().unwrap())
+    }
+
+    fn alloc_or_grow(&mut self, l : &Layout) -> Result<*mut u8, AllocErr> {
+        let st = self.st;
+
+        self.h.alloc(hole_layout(l), st).or_else(|_| {
+            self.grow(l)?;
+            self.h.alloc(hole_layout(l), st)
+        })
+    }
+
+    pub fn alloc_first_fit(&mut self, l : &Layout) -> Result<*mut u8, AllocErr> {
+        self.h.alloc_first_fit(hole_layout(l))
     }
 
     pub unsafe fn dealloc(&mut self, ptr : *mut u8, l : &Layout) {
-        let s = if l.size() < Holes::min_size() { Holes::min_size() } else { l.size() };
-        let s = align_up(s, mem::align_of::<Hole>());
-        let l = Layout::from_size_align(s, l.align()).unwrap();
+        self.h.dealloc(ptr, &hole_layout(l));
+    }
 
-        self.h.dealloc(ptr, &l);
+    /// Collects allocation statistics.
+    pub fn stats(&self) -> Stats {
+        let (holes, free_bytes, largest_hole) = self.h.usage();
+        let classes = self.c.stats();
+
+        Stats {
+            live_bytes : self.l + classes.iter().map(|c| c.live * c.size).sum::<usize>(),
+            large_live : self.n,
+            heap_size  : self.s,
+            strategy   : self.st,
+            classes, holes, free_bytes, largest_hole,
+        }
+    }
+
+    /// Share of the free memory which is unusable for the largest 
+    /// possible allocation, in percents.
+    pub fn fragmentation(&self) -> usize {
+        self.stats().fragmentation()
     }
 }
 
+/// Rounds the layout up to what the holes list can store.
+fn hole_layout(l : &Layout) -> Layout {
+    let s = if l.size() < Holes::min_size() { Holes::min_size() } else { l.size() };
+    let s = align_up(s, mem::align_of::<Hole>());
+    Layout::from_size_align(s, l.align()).unwrap()
+}
+
 unsafe impl Alloc for Heap {
     unsafe fn alloc(&mut self, l : Layout) -> Result<*mut u8, AllocErr> {
-        self.alloc_first_fit(&l)
+        self.allocate(&l)
     }
 
     unsafe fn dealloc(&mut self, ptr : *mut u8, l : Layout) {
-        self.dealloc(ptr, &l)
+        self.deallocate(ptr, &l)
+    }
+
+    unsafe fn realloc(&mut self, ptr : *mut u8, l : Layout, nl : Layout) -> Result<*mut u8, AllocErr> {
+        self.reallocate(ptr, &l, &nl)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr : *mut u8, l : Layout, nl : Layout) -> Result<(), CannotReallocInPlace> {
+        if Heap::grow_in_place(self, ptr, &l, &nl) { Ok(()) } else { Err(CannotReallocInPlace) }
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr : *mut u8, l : Layout, nl : Layout) -> Result<(), CannotReallocInPlace> {
+        if Heap::shrink_in_place(self, ptr, &l, &nl) { Ok(()) } else { Err(CannotReallocInPlace) }
     }
 
     default fn oom(&mut self, _ : AllocErr) -> ! {
@@ -88,35 +254,47 @@ unsafe impl Alloc for Heap {
 }
 
 #[cfg(feature = "use_spin")]
-pub struct HeapAllocator(Mutex<Heap>);
+pub struct HeapAllocator(SpinLock<Heap>);
 
 #[cfg(feature = "use_spin")]
 impl HeapAllocator {
     pub const fn blank() -> HeapAllocator {
-        HeapAllocator(Mutex::new(Heap::blank()))
+        HeapAllocator(SpinLock::new(Heap::blank()))
     }
 
     pub unsafe fn new(b : usize, s : usize) -> HeapAllocator {
-        HeapAllocator(Mutex::new(Heap::new(b, s)))
+        HeapAllocator(SpinLock::new(Heap::new(b, s)))
     }
 }
 
 #[cfg(feature = "use_spin")]
 unsafe impl<'a> Alloc for &'a HeapAllocator {
     unsafe fn alloc(&mut self, l : Layout) -> Result<*mut u8, AllocErr> {
-        self.0.lock().alloc_first_fit(&l)
+        self.0.lock().allocate(&l)
     }
 
     unsafe fn dealloc(&mut self, ptr : *mut u8, l : Layout) {
-        self.0.lock().dealloc(ptr, &l)
+        self.0.lock().deallocate(ptr, &l)
+    }
+
+    unsafe fn realloc(&mut self, ptr : *mut u8, l : Layout, nl : Layout) -> Result<*mut u8, AllocErr> {
+        self.0.lock().reallocate(ptr, &l, &nl)
+    }
+
+    unsafe fn grow_in_place(&mut self, ptr : *mut u8, l : Layout, nl : Layout) -> Result<(), CannotReallocInPlace> {
+        if self.0.lock().grow_in_place(ptr, &l, &nl) { Ok(()) } else { Err(CannotReallocInPlace) }
+    }
+
+    unsafe fn shrink_in_place(&mut self, ptr : *mut u8, l : Layout, nl : Layout) -> Result<(), CannotReallocInPlace> {
+        if self.0.lock().shrink_in_place(ptr, &l, &nl) { Ok(()) } else { Err(CannotReallocInPlace) }
     }
 }
 
 #[cfg(feature = "use_spin")]
 impl Deref for HeapAllocator {
-    type Target = Mutex<Heap>;
+    type Target = SpinLock<Heap>;
 
-    fn deref(&self) -> &Mutex<Heap> {
+    fn deref(&self) -> &SpinLock<Heap> {
         &self.0
     }
 }
@@ -51,16 +51,39 @@ impl Hole {
     }
 }
 
+/// Strategy used to pick a hole for an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// take the first hole big enough
+    FirstFit,
+    /// take the smallest hole big enough
+    BestFit,
+    /// take the first hole big enough after the previous allocation
+    NextFit,
+}
+
+#[cfg(feature = "best_fit")]
+pub const DEFAULT_STRATEGY : Strategy = Strategy::BestFit;
+
+#[cfg(all(feature = "next_fit", not(feature = "best_fit")))]
+pub const DEFAULT_STRATEGY : Strategy = Strategy::NextFit;
+
+#[cfg(not(any(feature = "best_fit", feature = "next_fit")))]
+pub const DEFAULT_STRATEGY : Strategy = Strategy::FirstFit;
+
 /// Sorted list of holes
 pub struct Holes {
-    f : Hole
+    f : Hole,
+    /// address right after the last allocation, used by next fit
+    c : usize,
 }
 
 impl Holes {
     /// Return a blank list of holes
     pub default const fn blank() -> Holes {
         Holes {
-            f : Hole { s : 0 , n : None }
+            f : Hole { s : 0 , n : None },
+            c : 0,
         }
     }
 
@@ -75,7 +98,8 @@ impl Holes {
         mem::replace(&mut *p, Hole { s : h_size, n : None });
 
         Holes {
-            f : Hole { n : Some(Unique::new_unchecked(p)), .. Default::default() }
+            f : Hole { n : Some(Unique::new_unchecked(p)), .. Default::default() },
+            c : h_addr,
         }
     }
 
@@ -89,13 +113,105 @@ impl Holes {
     pub fn alloc_first_fit(&mut self, l : Layout) -> Result<*mut u8, AllocErr> {
         assert!(l.size() >= Self::min_size());
 
-        alloc_first_fit(&mut self.f, l).map(|a| {
-            if let Some(p) = a.fr_p { dealloc(&mut self.f, p.a, p.s); }
-            if let Some(p) = a.ba_p { dealloc(&mut self.f, p.a, p.s); }
-            a.info.a as *mut u8
-        })
+        alloc_first_fit(&mut self.f, l).map(|a| self.take(a))
     } 
 
+    /// Searches the whole list for the smallest hole which fits.
+    pub fn alloc_best_fit(&mut self, l : Layout) -> Result<*mut u8, AllocErr> {
+        assert!(l.size() >= Self::min_size());
+
+        alloc_best_fit(&mut self.f, l).map(|a| self.take(a))
+    }
+
+    /// Same as first fit but the search continues from the last 
+    /// allocation and wraps around at the end of the list.
+    pub fn alloc_next_fit(&mut self, l : Layout) -> Result<*mut u8, AllocErr> {
+        assert!(l.size() >= Self::min_size());
+
+        let c = self.c;
+        alloc_next_fit(&mut self.f, l, c).map(|a| self.take(a))
+    }
+
+    /// Allocates by using the given strategy.
+    pub fn alloc(&mut self, l : Layout, st : Strategy) -> Result<*mut u8, AllocErr> {
+        match st {
+            Strategy::FirstFit => self.alloc_first_fit(l),
+            Strategy::BestFit  => self.alloc_best_fit(l),
+            Strategy::NextFit  => self.alloc_next_fit(l),
+        }
+    }
+
+    /// Returns paddings of the allocation back to the list.
+    fn take(&mut self, a : Alloc) -> *mut u8 {
+        if let Some(p) = a.fr_p { dealloc(&mut self.f, p.a, p.s); }
+        if let Some(p) = a.ba_p { dealloc(&mut self.f, p.a, p.s); }
+        self.c = a.info.a + a.info.s;
+        a.info.a as *mut u8
+    }
+
+    /// Returns the number of holes, the total free size
+    /// and the size of the largest hole.
+    pub fn usage(&self) -> (usize, usize, usize) {
+        let mut cur = self.f.n.as_ref().map(|n| unsafe { n.as_ref() });
+        let (mut n, mut total, mut largest) = (0, 0, 0);
+
+        while let Some(h) = cur {
+            n += 1;
+            total += h.s;
+            if h.s > largest { largest = h.s; }
+            cur = h.n.as_ref().map(|n| unsafe { n.as_ref() });
+        }
+
+        (n, total, largest)
+    }
+
+    /// Tries to grow the block `[addr, addr + size)` to `new_size` by 
+    /// taking the front of the hole lying right after it.
+    pub fn grow_in_place(&mut self, addr : usize, size : usize, new_size : usize) -> bool {
+        assert!(new_size >= size);
+
+        let extra = new_size - size;
+        if extra == 0 { return true; }
+
+        let mut prev = &mut self.f as *mut Hole;
+
+        unsafe {
+            while let Some(n) = (*prev).n.as_ref() {
+                let next = n.as_ref().info();
+
+                if next.a > addr + size { return false; }
+                if next.a < addr + size { prev = (*prev).next_unwrap() as *mut Hole; continue; }
+
+                if next.s == extra {
+                    (*prev).n = (*prev).next_unwrap().n.take();
+                } else if next.s >= extra + Self::min_size() {
+                    let rest = Hole { s : next.s - extra, n : (*prev).next_unwrap().n.take() };
+                    let ptr  = (next.a + extra) as *mut Hole;
+
+                    mem::replace(&mut *ptr, rest);
+                    (*prev).n = Some(Unique::new_unchecked(ptr));
+                } else {
+                    return false;
+                }
+
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Tries to shrink the block `[addr, addr + size)` to `new_size`
+    /// by returning its tail to the list.
+    pub unsafe fn shrink_in_place(&mut self, addr : usize, size : usize, new_size : usize) -> bool {
+        assert!(new_size <= size);
+
+        match size - new_size {
+            0 => true,
+            tail if tail >= Self::min_size() => { dealloc(&mut self.f, addr + new_size, tail); true }
+            _ => false,
+        }
+    }
+
     /// Frees the allocation given by `p` and `l`. 
     /// UB may occur for invalid arguments.
     pub unsafe fn dealloc(&mut self, p : *mut u8, l : &Layout) {
@@ -167,6 +283,50 @@ fn alloc_first_fit(mut prev : &mut Hole, l : Layout) -> Result<Alloc, AllocErr>
     }
 }
 
+/// Walks the whole list and takes the smallest hole the layout fits in.
+fn alloc_best_fit(head : &mut Hole, l : Layout) -> Result<Alloc, AllocErr> {
+    let mut best : Option<(*mut Hole, usize)> = None;
+    let mut prev = head as *mut Hole;
+
+    unsafe {
+        while let Some(cur) = (*prev).n.as_mut() {
+            let h = cur.as_ref().info();
+
+            if split_hole(h, &l).is_some() && best.map_or(true, |(_, s)| h.s < s) {
+                best = Some((prev, h.s));
+                if h.s == l.size() { break; }
+            }
+
+            prev = cur.as_mut() as *mut Hole;
+        }
+
+        match best {
+            Some((prev, _)) => {
+                let allocation = split_hole((*prev).next_unwrap().info(), &l).unwrap();
+                (*prev).n = (*prev).next_unwrap().n.take();
+                Ok(allocation)
+            }
+            None => Err(AllocErr::Exhausted { request : l }),
+        }
+    }
+}
+
+/// Runs first fit starting from the first hole at or after `cursor`,
+/// falling back to the head of the list if nothing fits past it.
+fn alloc_next_fit(head : &mut Hole, l : Layout, cursor : usize) -> Result<Alloc, AllocErr> {
+    let mut prev = head as *mut Hole;
+
+    unsafe {
+        while let Some(n) = (*prev).n.as_ref() {
+            if n.as_ref().info().a >= cursor { break; }
+            prev = (*prev).next_unwrap() as *mut Hole;
+        }
+
+        alloc_first_fit(&mut *prev, l.clone())
+            .or_else(|_| alloc_first_fit(head, l))
+    }
+}
+
 /// Deallocate the allocation provided by `addr` and `size`.
 fn dealloc(mut hole : &mut Hole, addr : usize, mut size : usize) {
     loop {
@@ -4,6 +4,11 @@ pub mod hole;
 
 pub mod heap;
 
+pub mod slab;
+
+#[cfg(feature = "heap_debug")]
+pub mod debug;
+
 pub mod frame;
 pub (super) use self::frame::{ Frame, FrameAllocator };
 
@@ -0,0 +1,167 @@
+// -*- mode: rust; -*-
+
+use core::{ fmt, ptr::Unique };
+
+use alloc::allocator::Layout;
+
+use super::hole::Strategy;
+
+/// Object sizes served by the slab caches, everything bigger
+/// or stricter aligned goes to the holes list.
+pub const SIZE_CLASSES : [usize ; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Free object header, forms a singly-linked list
+/// through the unused objects of a cache.
+struct Object {
+    n : Option<Unique<Object>>,
+}
+
+/// Cache of equally sized objects carved out of slabs
+/// taken from the underlying heap.
+pub struct Slab {
+    /// object size
+    s : usize,
+    /// free list head
+    f : Option<Unique<Object>>,
+    /// number of objects handed out
+    live  : usize,
+    /// number of objects on the free list
+    free  : usize,
+    /// number of slabs taken from the heap
+    slabs : usize,
+}
+
+impl Slab {
+    /// Return an empty cache for objects of size `s`.
+    pub const fn blank(s : usize) -> Slab {
+        Slab { s, f : None, live : 0, free : 0, slabs : 0 }
+    }
+
+    /// Size of the objects in this cache.
+    pub fn size(&self) -> usize {
+        self.s
+    }
+
+    /// Pops an object from the free list.
+    pub fn alloc(&mut self) -> Option<*mut u8> {
+        self.f.take().map(|mut o| {
+            self.f = unsafe { o.as_mut() }.n.take();
+            self.free -= 1;
+            self.live += 1;
+            o.as_ptr() as *mut u8
+        })
+    }
+
+    /// Pushes the object at `p` back to the free list.
+    /// UB may occur for pointers not obtained from this cache.
+    pub unsafe fn dealloc(&mut self, p : *mut u8) {
+        let o = p as *mut Object;
+        *o = Object { n : self.f.take() };
+        self.f = Some(Unique::new_unchecked(o));
+        self.free += 1;
+        self.live -= 1;
+    }
+
+    /// Carves the block `[b, b + s)` into objects and puts them on the free list.
+    /// `b` must be aligned to the object size.
+    pub unsafe fn refill(&mut self, b : usize, s : usize) {
+        assert!(b % self.s == 0, "unaligned slab");
+
+        (0..s / self.s).rev().for_each(|i| {
+            let o = (b + i * self.s) as *mut Object;
+            *o = Object { n : self.f.take() };
+            self.f = Some(Unique::new_unchecked(o));
+        });
+
+        self.free  += s / self.s;
+        self.slabs += 1;
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        SlabStats { size : self.s, live : self.live, free : self.free, slabs : self.slabs }
+    }
+}
+
+/// Set of caches, one per size class.
+pub struct Slabs {
+    c : [Slab ; 9],
+}
+
+impl Slabs {
+    pub const fn blank() -> Slabs {
+        Slabs {
+            c : [ Slab::blank(8),   Slab::blank(16),  Slab::blank(32)
+                , Slab::blank(64),  Slab::blank(128), Slab::blank(256)
+                , Slab::blank(512), Slab::blank(1024), Slab::blank(2048) ],
+        }
+    }
+
+    /// Returns the index of the size class which can hold `l`.
+    pub fn class(l : &Layout) -> Option<usize> {
+        let s = if l.size() > l.align() { l.size() } else { l.align() };
+        SIZE_CLASSES.iter().position(|&c| c >= s)
+    }
+
+    pub fn get_mut(&mut self, i : usize) -> &mut Slab {
+        &mut self.c[i]
+    }
+
+    pub fn stats(&self) -> [SlabStats ; 9] {
+        let mut s = [SlabStats::default() ; 9];
+        s.iter_mut().zip(self.c.iter()).for_each(|(s, c)| *s = c.stats());
+        s
+    }
+}
+
+/// Counters of a single size class.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SlabStats {
+    pub size  : usize,
+    pub live  : usize,
+    pub free  : usize,
+    pub slabs : usize,
+}
+
+/// Allocation statistics of the whole heap.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    /// strategy used by the holes list
+    pub strategy      : Strategy,
+    /// bytes handed out, rounded up to the size class or hole size
+    pub live_bytes    : usize,
+    /// live allocations served by the holes list
+    pub large_live    : usize,
+    /// counters per size class
+    pub classes       : [SlabStats ; 9],
+    /// size of the heap
+    pub heap_size     : usize,
+    /// bytes left in the holes list
+    pub free_bytes    : usize,
+    /// number of holes
+    pub holes         : usize,
+    /// size of the largest hole
+    pub largest_hole  : usize,
+}
+
+impl Stats {
+    /// Share of the free memory unusable for an allocation of
+    /// the largest hole size, in percents.
+    pub fn fragmentation(&self) -> usize {
+        if self.free_bytes == 0 { return 0; }
+        100 - self.largest_hole * 100 / self.free_bytes
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "heap ({:?}): {:#x} bytes, {:#x} live, {:#x} free in {} holes (largest {:#x}, {}% fragmented)",
+                 self.strategy, self.heap_size, self.live_bytes, self.free_bytes, self.holes,
+                 self.largest_hole, self.fragmentation())?;
+        writeln!(f, "\tlarge: {} live", self.large_live)?;
+
+        for c in self.classes.iter().filter(|c| c.slabs > 0) {
+            writeln!(f, "\t{:>5}: {} live, {} free, {} slabs", c.size, c.live, c.free, c.slabs)?;
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,57 @@
 // -*- mode: rust; -*-
 
-use kernel::mem::{
-    paging::{ self
-            , Page
-            , PageIter
-            , ActivePTable },
-    globals::PAGE_SIZE,
+use core::{ mem, ops::Deref };
+
+use kernel::{
+    sync::SpinLock,
+    smp::tlb::Shootdown,
+    mem::{ control
+         , paging::{ self
+                   , Page
+                   , PageIter
+                   , ActivePTable }
+         , globals::{ PAGE_SIZE
+                    , STACK_ALLOCATOR_SIZE } },
 };
 
 use super::FrameAllocator;
 
+/// Every stack has a guard page so there can't be more of them.
+const MAX_GUARDS : usize = STACK_ALLOCATOR_SIZE / 2;
+
+/// Unmapped page right below a stack, touching it means the stack overflowed.
+#[derive(Debug, Clone, Copy)]
+pub struct Guard {
+    pub page   : usize,
+    pub bottom : usize,
+    pub top    : usize,
+}
+
+/// Guard pages of the allocated stacks. A fixed table, as it's 
+/// consulted from the page fault and double fault handlers.
+static GUARDS : SpinLock<[Option<Guard> ; MAX_GUARDS]> = SpinLock::new([None ; MAX_GUARDS]);
+
+fn register_guard(g : Guard) {
+    if let Some(slot) = GUARDS.lock().iter_mut().find(|g| g.is_none()) {
+        *slot = Some(g);
+    }
+}
+
+fn unregister_guard(page : usize) {
+    if let Some(slot) = GUARDS.lock().iter_mut().find(|g| g.map_or(false, |g| g.page == page)) {
+        *slot = None;
+    }
+}
+
+/// Returns the guard page containing `addr` with its stack. Gives up 
+/// instead of spinning if the table is locked, so exception handlers 
+/// can't deadlock on it.
+pub fn guard_of(addr : usize) -> Option<Guard> {
+    GUARDS.try_lock().and_then(|gs| {
+        gs.iter().filter_map(|g| *g).find(|g| g.page <= addr && addr < g.page + PAGE_SIZE)
+    })
+}
+
 #[derive(Debug)]
 pub struct Stack {
     t : usize,
@@ -29,15 +71,85 @@ impl Stack {
     pub default fn bottom(&self) -> usize {
         self.b
     }
+
+    /// Number of pages, the guard page excluded.
+    pub fn pages(&self) -> usize {
+        (self.t - self.b) / PAGE_SIZE
+    }
+}
+
+/// Stack which goes back to the stack allocator when dropped. 
+/// It must not be dropped while the memory controller is locked.
+#[derive(Debug)]
+pub struct OwnedStack(Stack);
+
+impl OwnedStack {
+    /// Allocates a stack of `pages` pages.
+    pub fn alloc(pages : usize) -> Option<OwnedStack> {
+        control::controller().alloc(pages).map(OwnedStack)
+    }
+
+    /// Gives up the ownership, the stack stays allocated forever.
+    pub fn leak(self) -> Stack {
+        let s = Stack::new(self.0.t, self.0.b);
+        mem::forget(self);
+        s
+    }
+}
+
+impl Deref for OwnedStack {
+    type Target = Stack;
+
+    fn deref(&self) -> &Stack {
+        &self.0
+    }
 }
 
+impl Drop for OwnedStack {
+    fn drop(&mut self) {
+        control::controller().dealloc(Stack::new(self.0.t, self.0.b));
+    }
+}
+
+/// Capacity of the list of freed ranges.
+const MAX_FREE : usize = MAX_GUARDS;
+
 pub struct StackAllocator {
-    r : PageIter
+    r : PageIter,
+    /// freed ranges as first page and number of pages, guard pages included
+    f : [Option<(Page, usize)> ; MAX_FREE],
 }
 
 impl StackAllocator {
     pub fn new(r : PageIter) -> StackAllocator {
-        StackAllocator { r }
+        StackAllocator { r, f : [None ; MAX_FREE] }
+    }
+
+    /// Takes `n` pages from the first freed range large enough.
+    fn take_free(&mut self, n : usize) -> Option<Page> {
+        let slot = self.f.iter_mut().find(|r| r.map_or(false, |(_, len)| len >= n))?;
+        let (p, len) = slot.unwrap();
+
+        *slot = if len == n { None } else { Some((p + n, len - n)) };
+        Some(p)
+    }
+
+    /// Puts `n` pages starting at `p` on the free list, merging 
+    /// with the neighbouring ranges.
+    fn put_free(&mut self, mut p : Page, mut n : usize) {
+        for r in self.f.iter_mut() {
+            let cur = *r;
+            match cur {
+                Some((q, len)) if q + len == p => { p = q; n += len; *r = None; }
+                Some((q, len)) if p + n == q   => { n += len; *r = None; }
+                _ => {}
+            }
+        }
+
+        // a full list leaks the range, it's still never handed out twice
+        if let Some(r) = self.f.iter_mut().find(|r| r.is_none()) {
+            *r = Some((p, n));
+        }
     }
 
     pub default fn alloc<A : FrameAllocator>(
@@ -49,23 +161,59 @@ impl StackAllocator {
         
         if size == 0 { return None; }
 
-        let mut range = self.r.clone();
+        let (g, s, e) = match self.take_free(size + 1) {
+            Some(g) => (g, g + 1, g + size),
+            None    => {
+                let mut range = self.r.clone();
 
-        let guard_page = range.next();
-        
-        let stack_s = range.next();
-        let stack_e = if size == 0 { stack_s } else { range.nth(size - 2) };
+                let guard_page = range.next();
+                
+                let stack_s = range.next();
+                let stack_e = if size == 0 { stack_s } else { range.nth(size - 2) };
+
+                match (guard_page, stack_s, stack_e) {
+                    (Some(g), Some(s), Some(e)) => { self.r = range; (g, s, e) }
+                    _ => return None,
+                }
+            }
+        };
+
+        Page::range_inclusive(s,e).for_each(|p| at.map(p, paging::entry::WRITABLE | paging::entry::NO_EXECUTE, fr_a));
+
+        let stack_top = e.start_addr() + PAGE_SIZE;
+        register_guard(Guard { page : g.start_addr(), bottom : s.start_addr(), top : stack_top });
+
+        Some(Stack::new(stack_top, s.start_addr()))
+    }
+
+    /// Unmaps the stack, frees its frames and keeps its 
+    /// pages with the guard page for reuse.
+    pub fn dealloc<A : FrameAllocator>(
+          &mut self
+        , at   : &mut ActivePTable
+        , fr_a : &mut A
+        , st   : Stack
+        ) {
 
-        match (guard_page, stack_s, stack_e) {
-            (Some(_), Some(s), Some(e)) => {
-                self.r = range;
+        let s = Page::caddr(st.bottom());
+        let e = Page::caddr(st.top() - 1);
 
-                Page::range_inclusive(s,e).for_each(|p| at.map(p, paging::entry::WRITABLE, fr_a));
+        // one flush for the whole stack, done before the frames 
+        // can be handed out again as that needs the controller
+        {
+            let mut sd = Shootdown::new();
 
-                let stack_top = e.start_addr() + PAGE_SIZE;
-                Some(Stack::new(stack_top, s.start_addr()))
+            for p in Page::range_inclusive(s, e) {
+                if let Some(fr) = at.translate_page(p) {
+                    at.unmap_in(p, fr_a, &mut sd);
+                    fr_a.dealloc(fr);
+                }
             }
-            _ => None,
         }
+
+        let g = Page::caddr(st.bottom() - PAGE_SIZE);
+        unregister_guard(g.start_addr());
+
+        self.put_free(g, st.pages() + 1);
     }
 }
@@ -1,44 +1,582 @@
 // -*- mode: rust; -*-
 
+use spin::Once;
+
+use alloc::Vec;
+
+use core::{ cmp
+           , slice
+           , sync::atomic::{ AtomicUsize
+                           , ATOMIC_USIZE_INIT
+                           , Ordering } };
+
 use kernel::boot::BootInfo;
 
+use kernel::smp::tlb;
+
+use kernel::sync::{ SpinLock
+                  , SpinLockGuard };
+
 use kernel::mem::{
     globals::{ PAGE_SIZE
+             , ENTRY_COUNT
              , HEAP_START 
              , HEAP_SIZE 
-             , STACK_ALLOCATOR_SIZE },
+             , HEAP_MAX_SIZE
+             , STACK_START
+             , STACK_ALLOCATOR_SIZE 
+             , VGA_BUFFER
+             , IOREMAP_START
+             , IOREMAP_SIZE
+             , VirtualAddress
+             , PhysicalAddress },
     alloc::frame::{ AreaAllocator
                   , Frame
                   , FrameAllocator },
 };
 
+use x86_64::registers::control_regs;
+
 use super::{
     paging::{
         table::{ self
                , ActivePTable
                , InactivePTable },
-        entry::{ EFlags 
+        entry::{ Entry
+               , EFlags 
                , PRESENT
-               , WRITABLE },
+               , WRITABLE
+               , NO_EXECUTE
+               , COPY_ON_WRITE },
         page::{ Page 
-              , TempPage } },
+              , TempPage },
+        cache::CacheMode,
+        audit,
+        walk },
     alloc::{ stack
            , frame },
 };
 
+static MEMORY_CONTROLLER : Once<SpinLock<MemoryController>> = Once::new();
+
+/// Bumped whenever pages of the shared kernel half are unmapped, 
+/// remapped or change flags. Address spaces compare it on activation,
+/// as `invlpg` only drops translations tagged with the current PCID.
+static KERNEL_UNMAPS : AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Bytes mapped ahead of the heap top whenever the heap grows, so it 
+/// can still grow while the controller is held.
+const HEAP_RESERVE : usize = 64 * PAGE_SIZE;
+
+/// End of the mapped part of the heap window.
+static HEAP_MAPPED : AtomicUsize = ATOMIC_USIZE_INIT;
+
 pub struct MemoryController {
     _at   : table::ActivePTable,
     _fr_a : frame::AreaAllocator,
     _st_a : stack::StackAllocator,
+    _tp   : TempPage,
+    /// next free address of the `ioremap` window
+    _io   : VirtualAddress,
 }
 
+unsafe impl Send for MemoryController {}
+
 impl MemoryController {
     pub default fn alloc(&mut self, size : usize) -> Option<stack::Stack> {
         let &mut MemoryController { ref mut _at
                                   , ref mut _fr_a
-                                  , ref mut _st_a } = self;
+                                  , ref mut _st_a
+                                  , .. } = self;
         _st_a.alloc(_at, _fr_a, size)
     }
+
+    /// Gives a stack obtained by `alloc` back.
+    pub fn dealloc(&mut self, st : stack::Stack) {
+        let &mut MemoryController { ref mut _at
+                                  , ref mut _fr_a
+                                  , ref mut _st_a
+                                  , .. } = self;
+        _st_a.dealloc(_at, _fr_a, st);
+        KERNEL_UNMAPS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Physical frame of the active P4 table.
+    pub fn active_p4(&self) -> Frame {
+        Frame::caddr(control_regs::cr3().0 as usize)
+    }
+
+    /// Returns a new page table hierarchy sharing the kernel half 
+    /// (see `is_kernel_p4`) with the active one.
+    pub fn new_address_space(&mut self) -> Option<InactivePTable> {
+        let t = self.new_table()?;
+
+        {
+            let &mut MemoryController { ref mut _at
+                                      , ref mut _tp
+                                      , .. } = self;
+
+            {
+                let p4 = _tp.map_table_frame(&t.p4_frame, _at);
+
+                (0..ENTRY_COUNT)
+                    .filter(|&i| is_kernel_p4(i))
+                    .for_each(|i| p4[i] = _at.p4()[i].clone());
+            }
+
+            _tp.unmap(_at);
+        }
+
+        Some(t)
+    }
+
+    /// Frees every user frame and page table of `t` and its P4 frame.
+    /// `t` must not be the active table.
+    pub fn free_address_space(&mut self, mut t : InactivePTable) {
+        assert!(t.p4_frame != self.active_p4(), "freeing the active address space");
+
+        {
+            let &mut MemoryController { ref mut _at
+                                      , ref mut _fr_a
+                                      , ref mut _tp
+                                      , .. } = self;
+
+            _at.with(&mut t, _tp, |map| {
+                let p4 = map.p4_mut();
+
+                for i in (0..ENTRY_COUNT).filter(|&i| is_user_p4(i)) {
+                    if let Some(p3) = p4.next_table_mut(i) {
+                        for j in 0..ENTRY_COUNT {
+                            if let Some(p2) = p3.next_table_mut(j) {
+                                for k in 0..ENTRY_COUNT {
+                                    if let Some(p1) = p2.next_table_mut(k) {
+                                        for l in 0..ENTRY_COUNT {
+                                            if let Some(fr) = p1[l].pointed_frame() { _fr_a.dealloc(fr); }
+                                            p1[l].set_unused();
+                                        }
+                                    }
+                                    if let Some(fr) = p2[k].pointed_frame() { _fr_a.dealloc(fr); }
+                                    p2[k].set_unused();
+                                }
+                            }
+                            if let Some(fr) = p3[j].pointed_frame() { _fr_a.dealloc(fr); }
+                            p3[j].set_unused();
+                        }
+                    }
+                    if let Some(fr) = p4[i].pointed_frame() { _fr_a.dealloc(fr); }
+                    p4[i].set_unused();
+                }
+            });
+        }
+
+        self._fr_a.dealloc(t.p4_frame);
+    }
+
+    /// Number of user pages mapped in `t`.
+    pub fn user_pages(&mut self, t : &mut InactivePTable) -> usize {
+        let active = t.p4_frame == self.active_p4();
+        let mut n = 0;
+
+        {
+            let &mut MemoryController { ref mut _at
+                                      , ref mut _tp
+                                      , .. } = self;
+
+            let mut count = |_ : Page, _ : &mut Entry| n += 1;
+
+            if active {
+                _at.for_each_entry(is_user_p4, &mut count);
+            } else {
+                _at.with(t, _tp, |map| map.for_each_entry(is_user_p4, &mut count));
+            }
+        }
+
+        n
+    }
+
+    /// Duplicates the user half of `t` into a new address space. Writable
+    /// pages become read-only and `COPY_ON_WRITE` in both, every user 
+    /// frame gains an owner and is copied on the first write to it.
+    ///
+    /// `pages` collects the shared pages, the caller sizes it with 
+    /// `user_pages` before taking the controller. Returns `None`, with
+    /// `t` left as it was, if no frames or owner slots are left.
+    pub fn fork_address_space( &mut self
+                             , t     : &mut InactivePTable
+                             , pages : &mut Vec<(Page, Frame, EFlags)> ) -> Option<InactivePTable> 
+    {
+        let mut child = self.new_address_space()?;
+        let active = t.p4_frame == self.active_p4();
+        let mut ok = true;
+
+        {
+            let &mut MemoryController { ref mut _at
+                                      , ref mut _tp
+                                      , .. } = self;
+
+            let mut share = |p : Page, e : &mut Entry| {
+                if !ok { return; }
+
+                match share_cow(p, e) {
+                    Some(pg) => pages.push(pg),
+                    None     => ok = false,
+                }
+            };
+
+            if active {
+                _at.for_each_entry(is_user_p4, &mut share);
+            } else {
+                _at.with(t, _tp, |map| map.for_each_entry(is_user_p4, &mut share));
+            }
+        }
+
+        if !ok { self.unshare(t, pages); }
+
+        // other processors running this space may still 
+        // write through the old writable entries
+        if active { tlb::flush_user(); }
+
+        if !ok {
+            self.free_address_space(child);
+            return None;
+        }
+
+        self.map_into(&mut child, pages);
+        Some(child)
+    }
+
+    /// Undoes `share_cow` on the `pages` of `t` after a failed fork.
+    fn unshare(&mut self, t : &mut InactivePTable, pages : &[(Page, Frame, EFlags)]) {
+        let active = t.p4_frame == self.active_p4();
+
+        let &mut MemoryController { ref mut _at
+                                  , ref mut _tp
+                                  , .. } = self;
+
+        if active {
+            pages.iter().for_each(|&(p, ref fr, fl)| unshare_cow(_at.entry_mut(p), fr, fl));
+        } else {
+            _at.with(t, _tp, |map| {
+                pages.iter().for_each(|&(p, ref fr, fl)| unshare_cow(map.entry_mut(p), fr, fl));
+            });
+        }
+    }
+
+    /// Gives the active address space a private writable copy of the 
+    /// copy-on-write page containing `addr`. Returns `false` if the 
+    /// page isn't copy-on-write or no frames are left.
+    pub fn resolve_cow(&mut self, addr : VirtualAddress) -> bool {
+        let p = Page::caddr(addr);
+
+        let (old, fl) = match self._at.entry_mut(p) {
+            Some(ref e) if e.flags().contains(PRESENT | COPY_ON_WRITE) => (e.pointed_frame().unwrap(), e.flags()),
+            _ => return false,
+        };
+
+        // the last owner can simply take the frame over
+        let fr = if frame::owners(&old) == 1 { old } else {
+            let src = unsafe { slice::from_raw_parts(p.start_addr() as *const u8, PAGE_SIZE) };
+
+            match self.alloc_frame_with(|b| b.copy_from_slice(src)) {
+                Some(fr) => { self._fr_a.dealloc(old); fr }
+                None     => return false,
+            }
+        };
+
+        self._at.entry_mut(p).unwrap().set(&fr, (fl - COPY_ON_WRITE) | WRITABLE);
+        tlb::flush(p.start_addr());
+        true
+    }
+
+    /// Returns a fresh page table hierarchy with only the recursive mapping set.
+    pub fn new_table(&mut self) -> Option<InactivePTable> {
+        let fr = self._fr_a.alloc()?;
+        Some(InactivePTable::new(fr, &mut self._at, &mut self._tp))
+    }
+
+    /// Allocates a frame and lets `f` fill it through a temporary mapping.
+    pub fn alloc_frame_with<F>(&mut self, f : F) -> Option<Frame>
+    where
+        F : FnOnce(&mut [u8])
+    {
+        let fr = self._fr_a.alloc()?;
+        self.fill_frame(&fr, f);
+        Some(fr)
+    }
+
+    /// Gives a frame obtained by `alloc_frame_with` back.
+    pub fn free_frame(&mut self, fr : Frame) {
+        self._fr_a.dealloc(fr);
+    }
+
+    /// Lets `f` modify the frame `fr` through a temporary mapping.
+    pub fn fill_frame<F>(&mut self, fr : &Frame, f : F)
+    where
+        F : FnOnce(&mut [u8])
+    {
+        let addr = self._tp.map(fr, &mut self._at);
+        f(unsafe { slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) });
+        self._tp.unmap(&mut self._at);
+    }
+
+    /// Maps every `(page, frame, flags)` of `pages` in the inactive table `t`.
+    pub fn map_into(&mut self, t : &mut InactivePTable, pages : &[(Page, Frame, EFlags)]) {
+        let &mut MemoryController { ref mut _at
+                                  , ref mut _fr_a
+                                  , ref mut _tp
+                                  , .. } = self;
+
+        _at.with(t, _tp, |map| {
+            pages.iter().for_each(|&(p, ref fr, fl)| map.map_to(p, fr, fl, _fr_a));
+        });
+    }
+
+    /// Maps `[addr, addr + size)` to fresh frames with the given flags,
+    /// e.g. `USER_ACCESSIBLE` ones for code running in ring 3.
+    /// Returns `false` when no frames are left.
+    pub fn map_range(&mut self, addr : VirtualAddress, size : usize, fl : EFlags) -> bool {
+        let sp = Page::caddr(addr);
+        let ep = Page::caddr(addr + size - 1);
+
+        for p in Page::range_inclusive(sp, ep) {
+            match self._fr_a.alloc() {
+                Some(fr) => self._at.map_to(p, &fr, fl, &mut self._fr_a),
+                None     => return false,
+            }
+        }
+        true
+    }
+
+    /// Changes the flags of the mapped pages of `[addr, addr + size)`, 
+    /// e.g. to write protect data once it's initialized. Returns the 
+    /// number of pages changed.
+    pub fn protect(&mut self, addr : VirtualAddress, size : usize, fl : EFlags) -> usize {
+        if size == 0 { return 0; }
+
+        let sp = Page::caddr(addr);
+        let ep = Page::caddr(addr + size - 1);
+
+        let n = self._at.protect(sp, ep, fl);
+
+        // other address spaces may cache the old permissions under their PCID
+        if n > 0 && (sp.p4_idx()..ep.p4_idx() + 1).any(is_kernel_p4) {
+            KERNEL_UNMAPS.fetch_add(1, Ordering::SeqCst);
+        }
+        n
+    }
+
+    /// Points the mapped page containing `addr` to `fr`, keeping its 
+    /// flags, and returns the previous frame for the caller to free. 
+    /// Returns `None` if the page isn't mapped.
+    pub fn remap(&mut self, addr : VirtualAddress, fr : &Frame) -> Option<Frame> {
+        let p   = Page::caddr(addr);
+        let old = self._at.remap(p, fr)?;
+
+        // other address spaces may still reach `old` under their PCID
+        if is_kernel_p4(p.p4_idx()) {
+            KERNEL_UNMAPS.fetch_add(1, Ordering::SeqCst);
+        }
+        Some(old)
+    }
+
+    /// Prints the mappings of the active tables, see `walk::dump`.
+    pub fn dump(&self) -> usize {
+        walk::dump(&self._at)
+    }
+
+    /// Prints the mappings of the inactive tables `t`.
+    pub fn dump_inactive(&mut self, t : &mut InactivePTable) -> usize {
+        let &mut MemoryController { ref mut _at
+                                  , ref mut _tp
+                                  , .. } = self;
+        let mut n = 0;
+
+        _at.with(t, _tp, |map| n = walk::dump(map));
+        n
+    }
+
+    /// Flags of the page containing `addr`, `None` if it isn't mapped.
+    pub fn flags(&self, addr : VirtualAddress) -> Option<EFlags> {
+        self._at.flags(Page::caddr(addr))
+    }
+
+    /// Maps the device memory `[phys, phys + len)` with the memory type 
+    /// `mode` and returns the virtual address of `phys`. The addresses 
+    /// of the window are never reused.
+    pub fn ioremap(&mut self, phys : PhysicalAddress, len : usize, mode : CacheMode) -> Option<VirtualAddress> {
+        if len == 0 { return None; }
+
+        let sf = Frame::caddr(phys);
+        let ef = Frame::caddr(phys + len - 1);
+        let n  = ef.i - sf.i + 1;
+
+        if self._io + n * PAGE_SIZE > IOREMAP_START + IOREMAP_SIZE { return None; }
+
+        let sp = Page::caddr(self._io);
+        self._io += n * PAGE_SIZE;
+
+        let fl = WRITABLE | NO_EXECUTE | mode.flags();
+
+        for (p, fr) in Page::range_inclusive(sp, sp + (n - 1)).zip(Frame::range_inclusive(sf, ef)) {
+            self._at.map_to(p, &fr, fl, &mut self._fr_a);
+        }
+
+        Some(sp.start_addr() + phys % PAGE_SIZE)
+    }
+
+    /// Maps `fr` at the same virtual address unless it's already mapped,
+    /// e.g. for memory mapped registers or real mode code.
+    pub fn identity_map(&mut self, fr : &Frame, fl : EFlags) {
+        if self._at.translate_page(Page::caddr(fr.addr_ptr())).is_some() { return; }
+        self._at.idmap(fr, fl, &mut self._fr_a);
+    }
+
+    /// Backs the page containing `addr` with a fresh frame unless
+    /// it's already mapped. Returns `false` when no frames are left.
+    pub fn map_page(&mut self, addr : VirtualAddress) -> bool {
+        self.map_page_with(addr, WRITABLE | NO_EXECUTE)
+    }
+
+    /// Same as `map_page` with the given flags.
+    pub fn map_page_with(&mut self, addr : VirtualAddress, fl : EFlags) -> bool {
+        let p = Page::caddr(addr);
+
+        if self._at.translate_page(p).is_some() { return true; }
+
+        match self._fr_a.alloc() {
+            Some(fr) => { 
+                self._at.map_to(p, &fr, fl, &mut self._fr_a); 
+                true 
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns `true` for P4 entries shared by all address spaces: the
+/// first one holding the identity mapped kernel, the heap and the
+/// kernel stacks, and the higher half. The recursive entry is 
+/// per table and thus excluded.
+pub fn is_kernel_p4(i : usize) -> bool {
+    (i == 0 || i >= ENTRY_COUNT / 2) && i != ENTRY_COUNT - 1
+}
+
+/// Number of unmappings in the kernel half so far.
+pub fn kernel_unmaps() -> usize {
+    KERNEL_UNMAPS.load(Ordering::SeqCst)
+}
+
+/// Returns `true` for P4 entries private to an address space.
+pub fn is_user_p4(i : usize) -> bool {
+    i > 0 && i < ENTRY_COUNT / 2
+}
+
+/// Write protects a user page for a fork and adds an owner to its frame.
+/// Returns `None`, leaving the page alone, if the frame can't be shared.
+fn share_cow(p : Page, e : &mut Entry) -> Option<(Page, Frame, EFlags)> {
+    let fr = e.pointed_frame().unwrap();
+    let mut fl = e.flags();
+
+    frame::share(&fr).ok()?;
+
+    if fl.contains(WRITABLE) {
+        fl = (fl - WRITABLE) | COPY_ON_WRITE;
+        e.set(&fr, fl);
+    }
+
+    Some((p, fr, fl))
+}
+
+/// Drops the owner `share_cow` added to `fr`. A copy-on-write page 
+/// left with a single owner is simply writable again.
+fn unshare_cow(e : Option<&mut Entry>, fr : &Frame, fl : EFlags) {
+    frame::release(fr);
+
+    if let Some(e) = e {
+        if fl.contains(COPY_ON_WRITE) && frame::owners(fr) == 1 {
+            e.set(fr, (fl - COPY_ON_WRITE) | WRITABLE);
+        }
+    }
+}
+
+/// Returns the memory controller set up by `init`.
+pub fn controller() -> SpinLockGuard<'static, MemoryController> {
+    MEMORY_CONTROLLER.try().expect("memory controller is not initialized").lock()
+}
+
+/// Returns `true` if `addr` lies inside the virtual window reserved for the heap.
+pub fn in_heap_window(addr : VirtualAddress) -> bool {
+    addr >= HEAP_START && addr < HEAP_START + HEAP_MAX_SIZE
+}
+
+/// Maps the heap range `[addr, addr + size)` before the heap is extended over it.
+///
+/// The heap grows while its own lock is held, so the controller may be 
+/// held by this very processor, which allocates while changing mappings.
+/// Growing the heap maps `HEAP_RESERVE` more bytes ahead, which serve 
+/// the allocations made under the controller.
+pub fn map_heap(addr : VirtualAddress, size : usize) -> Result<(), ()> {
+    if !in_heap_window(addr) || !in_heap_window(addr + size - 1) { return Err(()); }
+
+    let end = addr + size;
+
+    let mut mc = match MEMORY_CONTROLLER.try().and_then(|mc| mc.lock_unless_held()) {
+        Some(mc) => mc,
+        None     => return if end <= HEAP_MAPPED.load(Ordering::SeqCst) { Ok(()) } else { Err(()) },
+    };
+
+    let sp = Page::caddr(addr);
+    let ep = Page::caddr(end - 1);
+
+    if !Page::range_inclusive(sp, ep).all(|p| mc.map_page(p.start_addr())) { return Err(()); }
+
+    // the reserve is best effort, only the requested range has to be there
+    let ahead = cmp::min(end + HEAP_RESERVE, HEAP_START + HEAP_MAX_SIZE);
+    let mut mapped = end;
+
+    while mapped < ahead && mc.map_page(mapped) {
+        mapped += PAGE_SIZE;
+    }
+
+    if mapped > HEAP_MAPPED.load(Ordering::SeqCst) {
+        HEAP_MAPPED.store(mapped, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+/// Returns `true` if this processor holds the memory controller, 
+/// faults it takes then can't be resolved.
+pub fn held_here() -> bool {
+    MEMORY_CONTROLLER.try().map_or(false, |mc| mc.held())
+}
+
+/// Resolves a not-present fault inside the heap window by mapping 
+/// the faulting page. Returns `false` if the fault isn't ours to handle.
+pub fn heap_fault(addr : VirtualAddress) -> bool {
+    if !in_heap_window(addr) { return false; }
+
+    match MEMORY_CONTROLLER.try().and_then(|mc| mc.lock_unless_held()) {
+        Some(mut mc) => mc.map_page(addr),
+        None         => false,
+    }
+}
+
+/// Maps the page containing `addr` with the flags `fl` on first 
+/// touch. Returns `false` if it can't be mapped.
+pub fn demand_fault(addr : VirtualAddress, fl : EFlags) -> bool {
+    match MEMORY_CONTROLLER.try().and_then(|mc| mc.lock_unless_held()) {
+        Some(mut mc) => mc.map_page_with(addr, fl),
+        None         => false,
+    }
+}
+
+/// Resolves a write to a copy-on-write page. Returns `false` 
+/// if the fault isn't ours to handle.
+pub fn cow_fault(addr : VirtualAddress) -> bool {
+    match MEMORY_CONTROLLER.try().and_then(|mc| mc.lock_unless_held()) {
+        Some(mut mc) => mc.resolve_cow(addr),
+        None         => false,
+    }
 }
 
 pub fn kernel_remap<A>(a : &mut A, b : &BootInfo) -> ActivePTable 
@@ -75,13 +613,13 @@ where
             }
         }
 
-        let vga_buf = &Frame::caddr(0xB8000);
-        map.idmap(vga_buf, WRITABLE, a);
+        let vga_buf = &Frame::caddr(VGA_BUFFER);
+        map.idmap(vga_buf, WRITABLE | NO_EXECUTE | CacheMode::WriteCombining.flags(), a);
 
         let mb_start = Frame::caddr(b.start_addr());
         let mb_end   = Frame::caddr(b.end_addr() - 1);
 
-        Frame::range_inclusive(mb_start, mb_end).for_each(|fr| map.idmap(&fr, PRESENT, a));
+        Frame::range_inclusive(mb_start, mb_end).for_each(|fr| map.idmap(&fr, PRESENT | NO_EXECUTE, a));
     });
 
     let old_t = at.switch(&new_t);
@@ -95,7 +633,24 @@ where
     at
 }
 
-pub fn init(boot_info : &BootInfo) -> MemoryController {
+/// Checks the tables set up by `kernel_remap` for W^X violations. 
+/// They are only reported unless the `strict_wx` feature is on.
+fn audit_wx(at : &ActivePTable, b : &BootInfo) {
+    let data = [
+        audit::DataRange { s : b.start_addr(), e : b.end_addr(), name : "multiboot information executable" },
+        audit::DataRange { s : VGA_BUFFER, e : VGA_BUFFER + PAGE_SIZE, name : "VGA buffer executable" },
+    ];
+
+    let n = audit::wx(at, &data);
+
+    if cfg!(feature = "strict_wx") && n > 0 {
+        panic!("{} W^X violations in the kernel mapping", n);
+    }
+
+    println!("w^x: {} violations", n);
+}
+
+pub fn init(boot_info : &BootInfo) {
     once!("mem::init cannot be called twice");
 
     let memory_map_tag = boot_info.memory_map_tag()
@@ -144,20 +699,28 @@ pub fn init(boot_info : &BootInfo) -> MemoryController {
     let heap_sp = Page::caddr(HEAP_START);
     let heap_ep = Page::caddr(HEAP_START + HEAP_SIZE - 1);
 
-    Page::range_inclusive(heap_sp, heap_ep).for_each(|p| active_table.map(p, WRITABLE, &mut frame_allocator));
+    Page::range_inclusive(heap_sp, heap_ep).for_each(|p| active_table.map(p, WRITABLE | NO_EXECUTE, &mut frame_allocator));
+    HEAP_MAPPED.store(HEAP_START + HEAP_SIZE, Ordering::SeqCst);
     
-    let stack_sp = heap_ep + 1;
+    let stack_sp = Page::caddr(STACK_START);
     let stack_ep = stack_sp + STACK_ALLOCATOR_SIZE;
     let stack_allocator = stack::StackAllocator::new(Page::range_inclusive(stack_sp, stack_ep));
 
     println!("\nkernel\t\t at: 0x{:<8x} - {:<8x}", kernel_start, kernel_end);
     println!("multiboot at: 0x{:<8x} - 0x{:<8x}", boot_info.start_addr(), boot_info.end_addr());
-    println!("heap \t at: 0x{:<8x} - 0x{:<8x}", HEAP_START, HEAP_START + HEAP_SIZE - 1);
+    println!("heap \t at: 0x{:<8x} - 0x{:<8x} (reserved up to 0x{:<8x})", 
+             HEAP_START, HEAP_START + HEAP_SIZE - 1, HEAP_START + HEAP_MAX_SIZE - 1);
     println!("stack \t\t at: 0x{:<8x} - 0x{:<8x}\n\n", stack_sp.i, stack_ep.i);
 
-    MemoryController {
+    audit_wx(&active_table, boot_info);
+
+    let temp_page = TempPage::new(Page { i : 0xCACABA }, &mut frame_allocator);
+
+    MEMORY_CONTROLLER.call_once(|| SpinLock::new(MemoryController {
         _at   : active_table,
         _fr_a : frame_allocator,
         _st_a : stack_allocator,
-    }
+        _tp   : temp_page,
+        _io   : IOREMAP_START,
+    }));
 }
@@ -9,4 +9,33 @@ pub (crate) const ENTRY_COUNT : usize =  512;
 pub (crate) const HEAP_START : usize = 0o0_000_010_000_000_000;
 pub (crate) const HEAP_SIZE  : usize = 100 * 1024; 
 
-pub (crate) const STACK_ALLOCATOR_SIZE : usize = 100;
+/// Size of the virtual window reserved for the heap, pages 
+/// beyond `HEAP_SIZE` are mapped on demand.
+pub (crate) const HEAP_MAX_SIZE : usize = 0o0_000_010_000_000_000;
+
+/// Kernel stacks are allocated right after the heap window.
+pub (crate) const STACK_START          : usize = HEAP_START + HEAP_MAX_SIZE;
+pub (crate) const STACK_ALLOCATOR_SIZE : usize = 512;
+
+/// Virtual window device memory is mapped into by `ioremap`.
+pub (crate) const IOREMAP_START : usize = 0o0_000_030_000_000_000;
+pub (crate) const IOREMAP_SIZE  : usize = 0o0_000_010_000_000_000;
+
+/// Memory below is never handed out by the frame allocator, it's
+/// left for real mode code such as the AP startup trampoline.
+pub (crate) const LOW_MEMORY_END : usize = 0x10_0000;
+
+/// Text mode buffer, identity mapped.
+pub (crate) const VGA_BUFFER : usize = 0xB8000;
+
+/// Lower half addresses available to user code, 
+/// the first P4 entry belongs to the kernel.
+pub (crate) const USER_START : usize = 0x0000_0080_0000_0000;
+pub (crate) const USER_END   : usize = 0x0000_8000_0000_0000;
+
+/// Initial stack of user programs grows down from the end of user space.
+pub (crate) const USER_STACK_TOP   : usize = USER_END;
+pub (crate) const USER_STACK_PAGES : usize = 16;
+
+/// Limit up to which user stacks grow on demand.
+pub (crate) const USER_STACK_MAX_PAGES : usize = 2048;
@@ -3,7 +3,35 @@
 pub mod globals;
 
 pub mod control;
-pub (in super::super) use self::control::init;
+pub (in super::super) use self::control::{ init, controller };
 
 pub mod alloc;
-mod paging;
+pub mod paging;
+
+#[cfg(feature = "heap_debug")]
+pub use self::alloc::debug::{ check_all as heap_check
+                            , dump as heap_dump };
+
+use self::alloc::{ slab::Stats
+                 , hole::Strategy };
+
+/// Allocation statistics of the kernel heap.
+pub fn heap_stats() -> Stats {
+    ::HEAP_ALLOCATOR.lock().stats()
+}
+
+/// Fragmentation of the free kernel heap, in percents.
+pub fn heap_fragmentation() -> usize {
+    ::HEAP_ALLOCATOR.lock().fragmentation()
+}
+
+/// Strategy the kernel heap uses for large allocations.
+pub fn heap_strategy() -> Strategy {
+    ::HEAP_ALLOCATOR.lock().strategy()
+}
+
+/// Switches the kernel heap to `st` for large allocations, 
+/// the holes already there are kept.
+pub fn set_heap_strategy(st : Strategy) {
+    ::HEAP_ALLOCATOR.lock().set_strategy(st);
+}
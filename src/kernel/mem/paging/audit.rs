@@ -0,0 +1,71 @@
+// -*- mode: rust; -*-
+
+//! # W^X audit
+//!
+//! Walks a page table hierarchy and reports pages which are writable 
+//! and executable at the same time, or executable while they should 
+//! only hold data. Flags are taken as the CPU sees them, see `walk`.
+
+use kernel::mem::globals::VirtualAddress;
+
+use super::{
+    map::Map,
+    walk,
+    entry::{ EFlags
+           , WRITABLE
+           , NO_EXECUTE },
+};
+
+/// Range `[s, e)` which must not be executable.
+pub struct DataRange {
+    pub s    : VirtualAddress,
+    pub e    : VirtualAddress,
+    pub name : &'static str,
+}
+
+/// Prints contiguous pages with the same problem as one line.
+struct Report {
+    /// current run as start, end and problem
+    run : Option<(VirtualAddress, VirtualAddress, &'static str)>,
+    n   : usize,
+}
+
+impl Report {
+    fn add(&mut self, s : VirtualAddress, size : usize, what : Option<&'static str>) {
+        let e = s + size;
+
+        match (self.run, what) {
+            (Some((rs, re, w)), Some(what)) if re == s && w == what => { self.run = Some((rs, e, w)); return; }
+            _ => {}
+        }
+
+        self.flush();
+        self.run = what.map(|w| (s, e, w));
+    }
+
+    fn flush(&mut self) {
+        if let Some((s, e, what)) = self.run.take() {
+            println!("w^x: {:#x} - {:#x} {}", s, e, what);
+            self.n += 1;
+        }
+    }
+}
+
+/// Problem of the mapping `[s, s + size)` with the flags `fl`, if any.
+fn check(s : VirtualAddress, size : usize, fl : EFlags, data : &[DataRange]) -> Option<&'static str> {
+    if fl.contains(NO_EXECUTE) { return None; }
+    if fl.contains(WRITABLE)   { return Some("writable and executable"); }
+
+    data.iter().find(|d| s < d.e && d.s < s + size).map(|d| d.name)
+}
+
+/// Reports every violation in the tables of `map`, pages of `data` 
+/// included, and returns the number of reported ranges.
+pub fn wx(map : &Map, data : &[DataRange]) -> usize {
+    let mut r = Report { run : None, n : 0 };
+
+    walk::walk(map, |m| r.add(m.vaddr, m.size, check(m.vaddr, m.size, m.flags, data)));
+
+    r.flush();
+    r.n
+}
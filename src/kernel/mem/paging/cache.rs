@@ -0,0 +1,138 @@
+// -*- mode: rust; -*-
+
+//! # Memory types
+//!
+//! The memory type of a page is picked from the IA32_PAT MSR by the 
+//! index `PAT | PCD | PWT` of its entry. `init` programs the PAT so 
+//! the first four slots keep their power-on types, which the tables 
+//! built before rely on, and the fifth one is write combining.
+//!
+//! | index | PAT PCD PWT | type |
+//! | ----- | ----------- | ---- |
+//! | 0     | 0   0   0   | WB   |
+//! | 1     | 0   0   1   | WT   |
+//! | 2     | 0   1   0   | UC-  |
+//! | 3     | 0   1   1   | UC   |
+//! | 4     | 1   0   0   | WC   |
+//!
+//! The PAT bit is bit 7 of a 4 KiB entry, where huge entries have 
+//! `HUGE_PAGE`, and bit 12 of a huge entry.
+
+use core::sync::atomic::{ AtomicBool, Ordering };
+
+use x86_64::{ instructions::tlb
+            , registers::msr::{ rdmsr, wrmsr } };
+
+use kernel::cpu;
+
+use super::entry::{ EFlags
+                  , HUGE_PAGE
+                  , NO_CACHE
+                  , WRITE_THROUGH };
+
+const IA32_PAT : u32 = 0x277;
+
+const PAT_UC    : u64 = 0x00;
+const PAT_WC    : u64 = 0x01;
+const PAT_WT    : u64 = 0x04;
+const PAT_WB    : u64 = 0x06;
+const PAT_UC_   : u64 = 0x07;
+
+/// Slots of the PAT as described above, the last three mirror the first ones.
+const PAT_VALUE : u64 = PAT_WB        | PAT_WT  <<  8 | PAT_UC_ << 16 | PAT_UC << 24
+                      | PAT_WC  << 32 | PAT_WT  << 40 | PAT_UC_ << 48 | PAT_UC << 56;
+
+/// PAT bit of a 4 KiB entry, the same bit as `HUGE_PAGE`.
+pub const PAT_4K : EFlags = HUGE_PAGE;
+
+/// PAT bit of a huge page entry.
+pub const PAT_HUGE : u64 = 1 << 12;
+
+static PAT_ENABLED : AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// write back, for normal memory
+    WriteBack,
+    /// write through
+    WriteThrough,
+    /// uncached, can be overridden to write combining by the MTRRs
+    UncachedMinus,
+    /// strongly uncached, for memory mapped registers
+    Uncached,
+    /// write combining, for frame buffers
+    WriteCombining,
+}
+
+impl CacheMode {
+    /// Index in the PAT. Write combining falls back to
+    /// `UncachedMinus` if the CPU has no PAT.
+    fn index(self) -> u64 {
+        match self {
+            CacheMode::WriteBack      => 0,
+            CacheMode::WriteThrough   => 1,
+            CacheMode::UncachedMinus  => 2,
+            CacheMode::Uncached       => 3,
+            CacheMode::WriteCombining => if PAT_ENABLED.load(Ordering::Relaxed) { 4 } else { 2 },
+        }
+    }
+
+    /// Entry flags selecting the mode on a 4 KiB page.
+    pub fn flags(self) -> EFlags {
+        let i = self.index();
+        let mut fl = EFlags::empty();
+
+        if i & 1 != 0 { fl |= WRITE_THROUGH; }
+        if i & 2 != 0 { fl |= NO_CACHE; }
+        if i & 4 != 0 { fl |= PAT_4K; }
+
+        fl
+    }
+
+    /// Mode the flags of a 4 KiB entry select, with the PAT set up by `init`.
+    pub fn of(fl : EFlags) -> CacheMode {
+        let i = (fl.contains(WRITE_THROUGH) as u8) 
+              | (fl.contains(NO_CACHE) as u8) << 1 
+              | (fl.contains(PAT_4K) as u8) << 2;
+
+        match i {
+            1 | 5 => CacheMode::WriteThrough,
+            2 | 6 => CacheMode::UncachedMinus,
+            3 | 7 => CacheMode::Uncached,
+            4 if PAT_ENABLED.load(Ordering::Relaxed) => CacheMode::WriteCombining,
+            _ => CacheMode::WriteBack,
+        }
+    }
+
+    /// Short name, as the memory type is usually written.
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheMode::WriteBack      => "wb",
+            CacheMode::WriteThrough   => "wt",
+            CacheMode::UncachedMinus  => "uc-",
+            CacheMode::Uncached       => "uc",
+            CacheMode::WriteCombining => "wc",
+        }
+    }
+
+    /// Entry bits selecting the mode on a huge page.
+    pub fn huge_bits(self) -> u64 {
+        let fl = self.flags();
+        (fl - PAT_4K).bits() | if fl.contains(PAT_4K) { PAT_HUGE } else { 0 }
+    }
+}
+
+/// Programs the PAT of the calling CPU, every CPU needs the same one.
+pub fn init() {
+    if !cpu::has(cpu::PAT) { return; }
+
+    unsafe {
+        if rdmsr(IA32_PAT) != PAT_VALUE {
+            asm!("wbinvd" :::: "volatile");
+            wrmsr(IA32_PAT, PAT_VALUE);
+            tlb::flush_all();
+        }
+    }
+
+    PAT_ENABLED.store(true, Ordering::SeqCst);
+}
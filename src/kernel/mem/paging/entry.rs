@@ -4,7 +4,7 @@ use kernel::boot::ElfSectionHeader;
 
 use kernel::mem::alloc::Frame;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Entry(u64);
 
 impl Entry {
@@ -44,11 +44,26 @@ bitflags! {
         const DIRTY             = 1 <<  6,
         const HUGE_PAGE         = 1 <<  7,
         const GLOBAL            = 1 <<  8,
+        /// ignored by the CPU, marks a page shared read-only after
+        /// a fork which gets copied on the first write
+        const COPY_ON_WRITE     = 1 <<  9,
         const NO_EXECUTE        = 1 << 63,
     }
 }
 
 impl EFlags {
+    /// Flags of a user page backing an ELF segment with the given `p_flags`.
+    pub fn from_elf_program_flags(fl : u32) -> EFlags {
+        use kernel::exec::elf::{ PF_W, PF_X };
+
+        let mut flags = PRESENT | USER_ACCESSIBLE;
+
+        if fl & PF_W != 0 { flags |= WRITABLE   ; }
+        if fl & PF_X == 0 { flags |= NO_EXECUTE ; }
+
+        flags
+    }
+
     pub fn from_elf_section_flags(section: &ElfSectionHeader) -> EFlags {
         use kernel::boot::{ ELF_SECTION_ALLOCATED
                           , ELF_SECTION_WRITABLE
@@ -6,18 +6,23 @@ use super::{
     page::Page,
     table::{ self
            , Table },
-    entry::{ EFlags
+    entry::{ Entry
+           , EFlags
            , HUGE_PAGE
-           , PRESENT },
+           , PRESENT
+           , WRITABLE
+           , NO_EXECUTE
+           , USER_ACCESSIBLE },
 }; 
 
-use kernel::mem::{
-    alloc::{ Frame
-           , FrameAllocator },
-    globals::{ PAGE_SIZE
-             , ENTRY_COUNT
-             , VirtualAddress 
-             , PhysicalAddress },
+use kernel::{
+    mem::{ alloc::{ Frame
+                  , FrameAllocator }
+         , globals::{ PAGE_SIZE
+                    , ENTRY_COUNT
+                    , VirtualAddress 
+                    , PhysicalAddress } },
+    smp::tlb::Shootdown,
 };
 
 pub struct Map { p4: Unique<Table<table::_L4>> }
@@ -76,14 +81,67 @@ impl Map {
           .or_else(huge_page)
     }
 
+    /// Returns the flags of `p`, `None` if it isn't mapped. Pages 
+    /// inside a huge page get the flags of the huge entry.
+    pub fn flags(&self, p : Page) -> Option<EFlags> {
+        let p3 = self.p4().next_table_ref(p.p4_idx())?;
+        let e3 = p3[p.p3_idx()].flags();
+        if e3.contains(PRESENT | HUGE_PAGE) { return Some(e3); }
+
+        let p2 = p3.next_table_ref(p.p3_idx())?;
+        let e2 = p2[p.p2_idx()].flags();
+        if e2.contains(PRESENT | HUGE_PAGE) { return Some(e2); }
+
+        let p1 = p2.next_table_ref(p.p2_idx())?;
+        let e1 = p1[p.p1_idx()].flags();
+        if e1.contains(PRESENT) { Some(e1) } else { None }
+    }
+
+    /// Returns the P1 entry of `p` if its tables exist.
+    pub fn entry_mut(&mut self, p : Page) -> Option<&mut Entry> {
+        self.p4_mut()
+            .next_table_mut(p.p4_idx())
+            .and_then(|p3| p3.next_table_mut(p.p3_idx()))
+            .and_then(|p2| p2.next_table_mut(p.p2_idx()))
+            .map(|p1| &mut p1[p.p1_idx()])
+    }
+
+    /// Calls `f` on every present 4 KiB page below the P4 entries
+    /// selected by `is`, passing its P1 entry.
+    pub fn for_each_entry<P, F>(&mut self, is : P, mut f : F)
+    where
+        P : Fn(usize) -> bool,
+        F : FnMut(Page, &mut Entry)
+    {
+        let p4 = self.p4_mut();
+
+        for i in (0..ENTRY_COUNT).filter(|&i| is(i)) {
+            let p3 = match p4.next_table_mut(i) { Some(t) => t, None => continue };
+
+            for j in 0..ENTRY_COUNT {
+                let p2 = match p3.next_table_mut(j) { Some(t) => t, None => continue };
+
+                for k in 0..ENTRY_COUNT {
+                    let p1 = match p2.next_table_mut(k) { Some(t) => t, None => continue };
+
+                    for l in (0..ENTRY_COUNT).filter(|&l| p1[l].flags().contains(PRESENT)) {
+                        f(Page { i : (i << 27) | (j << 18) | (k << 9) | l }, &mut p1[l]);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn map_to<A>(&mut self, p : Page, fr : &Frame, fl : EFlags, a : &mut A)
     where 
         A : FrameAllocator
     {
+        let tfl = fl & USER_ACCESSIBLE;
+
         let p4 = self.p4_mut();
-        let p3 = p4.next_table_create(p.p4_idx(), a);
-        let p2 = p3.next_table_create(p.p3_idx(), a);
-        let p1 = p2.next_table_create(p.p2_idx(), a);
+        let p3 = p4.next_table_create(p.p4_idx(), tfl, a);
+        let p2 = p3.next_table_create(p.p3_idx(), tfl, a);
+        let p1 = p2.next_table_create(p.p2_idx(), tfl, a);
 
         assert!(p1[p.p1_idx()].is_unused());
         p1[p.p1_idx()].set(fr, fl | PRESENT);
@@ -105,13 +163,78 @@ impl Map {
         self.map_to(p, &fr, fl, a)
     }
 
-    pub fn unmap<A>(&mut self, p : Page, _ : &mut A)
+    /// Gives the mapped pages of `[s, e]` the access flags `fl`, keeping 
+    /// their frames and other flags (caching, copy-on-write, ...), and 
+    /// flushes them from the TLBs of all processors. Returns the number 
+    /// of pages changed, unmapped ones are skipped.
+    pub fn protect(&mut self, s : Page, e : Page, fl : EFlags) -> usize {
+        let mut sd = Shootdown::new();
+        Page::range_inclusive(s, e).filter(|&p| self.protect_in(p, fl, &mut sd)).count()
+    }
+
+    /// Same as `protect` for the single page `p`, leaving the flush to 
+    /// the batch `sd`. Returns `false` if `p` isn't mapped.
+    pub fn protect_in(&mut self, p : Page, fl : EFlags, sd : &mut Shootdown) -> bool {
+        let mapped = self.entry_mut(p).map_or(false, |e| e.flags().contains(PRESENT));
+        if !mapped { return false; }
+
+        // user pages have to be reachable through every level
+        let p1 = self.p1_mut(p, fl & USER_ACCESSIBLE).unwrap();
+        let e  = &mut p1[p.p1_idx()];
+
+        let fr  = e.pointed_frame().unwrap();
+        let old = e.flags() - (WRITABLE | USER_ACCESSIBLE | NO_EXECUTE);
+
+        e.set(&fr, old | fl | PRESENT); 
+        sd.add(p.start_addr()); 
+        true
+    }
+
+    /// Points the mapped page `p` to `fr`, keeping its flags, and returns
+    /// the previous frame once no TLB refers to it anymore.
+    pub fn remap(&mut self, p : Page, fr : &Frame) -> Option<Frame> {
+        let mut sd = Shootdown::new();
+
+        let e   = self.entry_mut(p)?;
+        let old = e.pointed_frame()?;
+        let fl  = e.flags();
+
+        e.set(fr, fl);
+        sd.add(p.start_addr());
+
+        Some(old)
+    }
+
+    /// P1 table holding `p` if there is one, the entries leading 
+    /// to it gain `fl`. Callers check that `p` is mapped first, the 
+    /// path to a missing page must not be widened.
+    fn p1_mut(&mut self, p : Page, fl : EFlags) -> Option<&mut Table<table::_L1>> {
+        let p4 = self.p4_mut();
+        widen(&mut p4[p.p4_idx()], fl);
+
+        let p3 = p4.next_table_mut(p.p4_idx())?;
+        widen(&mut p3[p.p3_idx()], fl);
+
+        let p2 = p3.next_table_mut(p.p3_idx())?;
+        widen(&mut p2[p.p2_idx()], fl);
+
+        p2.next_table_mut(p.p2_idx())
+    }
+
+    /// Unmaps `p` and flushes it from the TLBs of all processors.
+    pub fn unmap<A>(&mut self, p : Page, a : &mut A)
     where 
         A : FrameAllocator
     {
-        use x86_64::instructions::tlb;
-        use x86_64::VirtualAddress;
+        let mut sd = Shootdown::new();
+        self.unmap_in(p, a, &mut sd);
+    }
 
+    /// Same as `unmap`, leaving the flush to the batch `sd`.
+    pub fn unmap_in<A>(&mut self, p : Page, _ : &mut A, sd : &mut Shootdown)
+    where 
+        A : FrameAllocator
+    {
         assert!(self.translate_vtop(p.start_addr()).is_some());
 
         let p1 = self.p4_mut()
@@ -121,6 +244,15 @@ impl Map {
                      .expect("mapping code does not support huge pages");
 
         p1[p.p1_idx()].set_unused();
-        tlb::flush(VirtualAddress(p.start_addr()));
+        sd.add(p.start_addr());
     }
 }
+
+/// Adds `fl` to the entry `e` if it points to a table.
+fn widen(e : &mut Entry, fl : EFlags) {
+    let old = e.flags();
+    if fl.is_empty() || old.contains(fl) || !old.contains(PRESENT) || old.contains(HUGE_PAGE) { return; }
+
+    let fr = e.pointed_frame().unwrap();
+    e.set(&fr, old | fl);
+}
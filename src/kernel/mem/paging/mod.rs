@@ -9,3 +9,9 @@ pub mod page;
 pub (super) use self::page::{ Page, PageIter };
 
 mod map;
+
+pub mod cache;
+
+pub mod walk;
+
+pub mod audit;
@@ -11,7 +11,8 @@ use kernel::mem::{
 };
 
 use super::{
-    entry::WRITABLE,    
+    entry::{ WRITABLE
+           , NO_EXECUTE },
     table::{ self
            , ActivePTable },
 };
@@ -99,7 +100,7 @@ impl TempPage {
         // TODO 
         assert!(at.translate_page(self.p).is_none(),
                 "temporary page is already mapped");
-        at.map_to(self.p, fr, WRITABLE, &mut self.a);
+        at.map_to(self.p, fr, WRITABLE | NO_EXECUTE, &mut self.a);
         self.p.start_addr()
     }
 
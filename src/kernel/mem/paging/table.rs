@@ -23,6 +23,7 @@ use super::{
     page::TempPage,
     map::Map,
     entry::{ Entry
+           , EFlags
            , HUGE_PAGE
            , PRESENT
            , WRITABLE },
@@ -82,15 +83,22 @@ where
             .map(|addr| unsafe { &mut *(addr as *mut _) })
     }
 
-    pub default fn next_table_create<A>(&mut self, i : usize, a : &mut A) -> &mut Table<L::NL>
+    /// Returns the next level table, creating it if needed. `fl` is added 
+    /// to the entry, e.g. `USER_ACCESSIBLE` has to be set on every level
+    /// for user pages to be reachable from ring 3.
+    pub default fn next_table_create<A>(&mut self, i : usize, fl : EFlags, a : &mut A) -> &mut Table<L::NL>
     where 
         A : FrameAllocator
     {
         if self.next_table_ref(i).is_none() {
             assert!(!self.es[i].flags().contains(HUGE_PAGE), "huge page mapping is unsupported (TODO)");
             let fr = a.alloc().expect("no frames available");
-            self.es[i].set(&fr, PRESENT | WRITABLE);
+            self.es[i].set(&fr, PRESENT | WRITABLE | fl);
             self.next_table_mut(i).unwrap().zero();
+        } else if !self.es[i].flags().contains(fl) {
+            let fr = self.es[i].pointed_frame().unwrap();
+            let old_fl = self.es[i].flags();
+            self.es[i].set(&fr, old_fl | fl);
         }
         self.next_table_mut(i).unwrap()
     }
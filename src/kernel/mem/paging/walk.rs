@@ -0,0 +1,188 @@
+// -*- mode: rust; -*-
+
+//! # Page table walker
+//!
+//! `walk` visits every leaf mapping of a table hierarchy, 4 KiB pages 
+//! and huge ones alike, with the flags as the CPU sees them: writable
+//! only if every level allows it, executable unless some level has 
+//! `NO_EXECUTE`. The recursive entry is skipped. `dump` prints the 
+//! mappings merged into contiguous ranges.
+//!
+//! An `InactivePTable` is walked from inside `ActivePTable::with`.
+
+use kernel::mem::globals::{ ENTRY_COUNT
+                          , PAGE_SIZE
+                          , VirtualAddress
+                          , PhysicalAddress };
+
+use super::{
+    map::Map,
+    cache::CacheMode,
+    table::{ Table
+           , HLayer
+           , _L3
+           , _L2
+           , _L1 },
+    entry::{ EFlags
+           , PRESENT
+           , WRITABLE
+           , USER_ACCESSIBLE
+           , ACCESSED
+           , DIRTY
+           , HUGE_PAGE
+           , GLOBAL
+           , COPY_ON_WRITE
+           , NO_EXECUTE },
+};
+
+/// Leaf mapping found by `walk`.
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    pub vaddr : VirtualAddress,
+    pub paddr : PhysicalAddress,
+    pub size  : usize,
+    pub flags : EFlags,
+}
+
+/// Size covered by one entry of a level.
+pub trait Span {
+    fn span() -> usize;
+}
+
+impl Span for _L3 { fn span() -> usize { PAGE_SIZE * ENTRY_COUNT * ENTRY_COUNT } }
+impl Span for _L2 { fn span() -> usize { PAGE_SIZE * ENTRY_COUNT } }
+impl Span for _L1 { fn span() -> usize { PAGE_SIZE } }
+
+/// Flags of `e` as restricted by the upper level flags `up`.
+pub fn effective(up : EFlags, e : EFlags) -> EFlags {
+    let mut f = e;
+    if !up.contains(WRITABLE)        { f.remove(WRITABLE); }
+    if !up.contains(USER_ACCESSIBLE) { f.remove(USER_ACCESSIBLE); }
+    if  up.contains(NO_EXECUTE)      { f.insert(NO_EXECUTE); }
+    f
+}
+
+/// Visits the mappings below a table whose first entry maps `base`.
+pub trait Walk {
+    fn walk<F>(&self, base : VirtualAddress, up : EFlags, f : &mut F)
+    where
+        F : FnMut(Mapping);
+}
+
+impl Walk for Table<_L1> {
+    fn walk<F>(&self, base : VirtualAddress, up : EFlags, f : &mut F)
+    where
+        F : FnMut(Mapping)
+    {
+        for i in (0..ENTRY_COUNT).filter(|&i| self[i].flags().contains(PRESENT)) {
+            f(Mapping { 
+                vaddr : base + i * PAGE_SIZE, 
+                paddr : self[i].pointed_frame().unwrap().addr_ptr(),
+                size  : PAGE_SIZE, 
+                flags : effective(up, self[i].flags()),
+            });
+        }
+    }
+}
+
+impl<L> Walk for Table<L>
+where
+    L : HLayer + Span,
+    Table<L::NL> : Walk
+{
+    fn walk<F>(&self, base : VirtualAddress, up : EFlags, f : &mut F)
+    where
+        F : FnMut(Mapping)
+    {
+        for i in (0..ENTRY_COUNT).filter(|&i| self[i].flags().contains(PRESENT)) {
+            let v  = base + i * L::span();
+            let fl = effective(up, self[i].flags());
+
+            if fl.contains(HUGE_PAGE) {
+                let paddr = self[i].pointed_frame().unwrap().addr_ptr();
+                f(Mapping { vaddr : v, paddr, size : L::span(), flags : fl });
+            } else if let Some(t) = self.next_table_ref(i) {
+                t.walk(v, fl, f);
+            }
+        }
+    }
+}
+
+/// Sign extended address of the P4 entry `i`.
+fn p4_base(i : usize) -> VirtualAddress {
+    let a = i << 39;
+    if i >= ENTRY_COUNT / 2 { a | 0xFFFF_0000_0000_0000 } else { a }
+}
+
+/// Calls `f` on every leaf mapping of `map` in address order.
+pub fn walk<F>(map : &Map, mut f : F)
+where
+    F : FnMut(Mapping)
+{
+    let p4 = map.p4();
+
+    for i in (0..ENTRY_COUNT - 1).filter(|&i| p4[i].flags().contains(PRESENT)) {
+        if let Some(p3) = p4.next_table_ref(i) {
+            p3.walk(p4_base(i), p4[i].flags(), &mut f);
+        }
+    }
+}
+
+/// Prints `fl` as `wxugcnt`, dashes for the missing flags.
+fn print_flags(fl : EFlags) {
+    let bits = [
+        (fl.contains(WRITABLE),         'w'),
+        (!fl.contains(NO_EXECUTE),      'x'),
+        (fl.contains(USER_ACCESSIBLE),  'u'),
+        (fl.contains(GLOBAL),           'g'),
+        (fl.contains(COPY_ON_WRITE),    'c'),
+    ];
+
+    bits.iter().for_each(|&(on, c)| print!("{}", if on { c } else { '-' }));
+
+    print!(" {}", CacheMode::of(fl).name());
+}
+
+/// Prints the mappings of `map` as `vaddr range -> paddr range [flags]`,
+/// pages contiguous in both spaces with the same flags form one line.
+/// Returns the number of lines.
+pub fn dump(map : &Map) -> usize {
+    // the CPU sets these as it goes, they'd only split the ranges
+    let noise = ACCESSED | DIRTY;
+
+    let mut run : Option<Mapping> = None;
+    let mut n = 0;
+
+    {
+        let mut line = |m : Mapping| {
+            print!("{:#014x}-{:#014x} -> {:#012x}-{:#012x} [", m.vaddr, m.vaddr + m.size, m.paddr, m.paddr + m.size);
+            print_flags(m.flags);
+            println!("]");
+            n += 1;
+        };
+
+        walk(map, |m| {
+            // bit 7 is `HUGE_PAGE` on huge entries but the PAT bit of 
+            // 4 KiB ones, which selects their memory type. The PAT bit of
+            // huge entries isn't kept in `EFlags`, their type is decoded 
+            // from PCD and PWT alone.
+            let fl = if m.size == PAGE_SIZE { m.flags } else { m.flags - HUGE_PAGE };
+            let m  = Mapping { flags : fl - noise, ..m };
+
+            let extends = run.map_or(false, |r| r.vaddr + r.size == m.vaddr 
+                                             && r.paddr + r.size == m.paddr 
+                                             && r.flags == m.flags);
+
+            if extends { 
+                if let Some(ref mut r) = run { r.size += m.size; }
+            } else {
+                if let Some(r) = run.take() { line(r); }
+                run = Some(m);
+            }
+        });
+
+        if let Some(r) = run.take() { line(r); }
+    }
+
+    n
+}
@@ -6,4 +6,13 @@ pub mod boot;
 pub mod vga;
 pub mod mem;
 pub mod bits;
+pub mod cpu;
+pub mod fpu;
 pub mod interrupt;
+pub mod thread;
+pub mod sync;
+pub mod user;
+pub mod syscall;
+pub mod exec;
+pub mod process;
+pub mod smp;
@@ -0,0 +1,135 @@
+// -*- mode: rust; -*-
+
+//! # Processes
+//!
+//! A process owns an address space and runs as one or more threads, 
+//! the scheduler loads its page tables whenever one of them is 
+//! switched in. Kernel threads don't have one and run on whatever 
+//! tables were loaded before.
+
+use alloc::{ Vec
+           , arc::Arc };
+
+use core::sync::atomic::{ AtomicUsize
+                        , ATOMIC_USIZE_INIT
+                        , Ordering };
+
+use kernel::{
+    user,
+    thread,
+    sync::SpinLock,
+    mem::{ globals::{ PAGE_SIZE
+                    , USER_START
+                    , USER_STACK_TOP
+                    , USER_STACK_MAX_PAGES }
+         , paging::entry::EFlags },
+    exec::{ self
+          , elf::{ Elf
+                 , Error } },
+};
+
+mod space;
+pub use self::space::{ init
+                     , activate_kernel
+                     , AddressSpace };
+
+static NEXT_PID : AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Anonymous mappings are placed from here upwards.
+pub const MMAP_BASE : usize = USER_START + 0x10_0000_0000;
+
+/// End of the `mmap` window, the user stack may grow down to it.
+pub const MMAP_END  : usize = USER_STACK_TOP - USER_STACK_MAX_PAGES * PAGE_SIZE;
+
+/// User memory reserved by `mmap`, backed by frames on first touch.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub s  : usize,
+    pub e  : usize,
+    pub fl : EFlags,
+}
+
+#[derive(Clone)]
+struct Regions {
+    v    : Vec<Region>,
+    /// where the next region goes
+    next : usize,
+}
+
+pub struct Process {
+    pid     : usize,
+    name    : &'static str,
+    space   : AddressSpace,
+    regions : SpinLock<Regions>,
+}
+
+impl Process {
+    /// Creates a process with an empty user half.
+    pub fn new(name : &'static str) -> Option<Process> {
+        Some(Process {
+            pid     : NEXT_PID.fetch_add(1, Ordering::SeqCst) + 1,
+            space   : AddressSpace::new()?,
+            regions : SpinLock::new(Regions { v : Vec::new(), next : MMAP_BASE }),
+            name,
+        })
+    }
+
+    /// Loads the ELF image `data` into a new process and starts 
+    /// its main thread at the entry point. Returns the process.
+    pub fn exec(name : &'static str, data : &[u8], args : &[&str], env : &[&str]) -> Result<Arc<Process>, Error> {
+        let elf = Elf::parse(data)?;
+
+        let p = Arc::new(Process::new(name).ok_or(Error::NoMemory)?);
+        let image = exec::load(&elf, &mut p.space.table(), args, env)?;
+
+        thread::spawn_in(name, p.clone(), move || unsafe {
+            user::jump_to_user(image.entry, image.stack)
+        });
+
+        Ok(p)
+    }
+
+    /// Creates a child process sharing all user pages copy-on-write.
+    /// The child has no threads, the caller spawns them with `thread::spawn_in`.
+    pub fn fork(&self) -> Option<Arc<Process>> {
+        Some(Arc::new(Process {
+            pid     : NEXT_PID.fetch_add(1, Ordering::SeqCst) + 1,
+            space   : self.space.fork()?,
+            regions : SpinLock::new(self.regions.lock().clone()),
+            name    : self.name,
+        }))
+    }
+
+    pub default fn pid(&self) -> usize {
+        self.pid
+    }
+
+    pub default fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn space(&self) -> &AddressSpace {
+        &self.space
+    }
+
+    /// Reserves `len` bytes of the `mmap` window to be mapped with `fl` 
+    /// on demand. Returns their address, `None` if they don't fit.
+    pub fn add_region(&self, len : usize, fl : EFlags) -> Option<usize> {
+        let mut r = self.regions.lock();
+        let s = r.next;
+
+        match s.checked_add(len) {
+            Some(e) if e <= MMAP_END => {
+                r.v.push(Region { s, e, fl });
+                r.next = e;
+                Some(s)
+            }
+            _ => None,
+        }
+    }
+
+    /// Flags of the region containing `addr`, if any.
+    pub fn region_flags(&self, addr : usize) -> Option<EFlags> {
+        self.regions.lock().v.iter().find(|r| r.s <= addr && addr < r.e).map(|r| r.fl)
+    }
+}
@@ -0,0 +1,184 @@
+// -*- mode: rust; -*-
+
+use spin::Once;
+
+use alloc::Vec;
+
+use core::sync::atomic::{ AtomicBool, AtomicUsize, ATOMIC_USIZE_INIT, Ordering };
+
+use kernel::{
+    cpu,
+    sync::SpinLock,
+    mem::{ self
+         , control
+         , alloc::frame::Frame
+         , paging::table::InactivePTable },
+    smp::tlb,
+};
+
+/// Number of process context identifiers, 0 is the kernel's.
+const PCIDS : usize = 4096;
+
+const CR4_PCIDE     : u64 = 1 << 17;
+const CR3_NO_FLUSH  : u64 = 1 << 63;
+
+/// P4 frame of the kernel's own address space.
+static KERNEL_P4 : Once<Frame> = Once::new();
+
+static PCID_ENABLED : AtomicBool = AtomicBool::new(false);
+
+/// `control::kernel_unmaps` when PCID 0 was last flushed.
+static KERNEL_SEEN : AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set while PCID 0 may hold user translations, i.e. an address 
+/// space which got no PCID of its own was loaded since the last flush.
+static KERNEL_DIRTY : AtomicBool = AtomicBool::new(false);
+
+/// Bitmap of PCIDs in use, 0 is never handed out.
+static PCID_MAP : SpinLock<[u64 ; PCIDS / 64]> = SpinLock::new([0 ; PCIDS / 64]);
+
+fn alloc_pcid() -> Option<u16> {
+    if !PCID_ENABLED.load(Ordering::Relaxed) { return None; }
+
+    let mut map = PCID_MAP.lock();
+
+    (1..PCIDS).find(|&i| map[i / 64] & (1 << (i % 64)) == 0).map(|i| {
+        map[i / 64] |= 1 << (i % 64);
+        i as u16
+    })
+}
+
+fn free_pcid(id : u16) {
+    let i = id as usize;
+    PCID_MAP.lock()[i / 64] &= !(1 << (i % 64));
+}
+
+unsafe fn write_cr3(v : u64) {
+    asm!("mov cr3, $0" :: "r"(v) : "memory" : "intel", "volatile");
+    tlb::set_active((v & 0x000F_FFFF_FFFF_F000) as usize);
+}
+
+fn read_cr3() -> u64 {
+    let v : u64;
+    unsafe { asm!("mov $0, cr3" : "=r"(v) ::: "intel", "volatile") };
+    v
+}
+
+/// Remembers the kernel address space and turns on PCIDs if 
+/// the CPU has them, the kernel keeps PCID 0.
+pub fn init() {
+    KERNEL_P4.call_once(|| mem::controller().active_p4());
+
+    if cpu::has(cpu::PCID) {
+        unsafe {
+            let cr4 : u64;
+            asm!("mov $0, cr4" : "=r"(cr4) ::: "intel", "volatile");
+            asm!("mov cr4, $0" :: "r"(cr4 | CR4_PCIDE) : "memory" : "intel", "volatile");
+        }
+        PCID_ENABLED.store(true, Ordering::SeqCst);
+    }
+
+    println!("pcid: {}", if PCID_ENABLED.load(Ordering::SeqCst) { "enabled" } else { "unsupported" });
+}
+
+/// Switches to the kernel address space.
+pub fn activate_kernel() {
+    let p4 = KERNEL_P4.try().expect("process::init was not called").addr_ptr() as u64;
+    if read_cr3() & 0x000F_FFFF_FFFF_F000 == p4 { return; }
+
+    // both are checked, each resets its state
+    let stale = stale(&KERNEL_SEEN) | KERNEL_DIRTY.swap(false, Ordering::Relaxed);
+    let nf = if PCID_ENABLED.load(Ordering::Relaxed) && !stale { CR3_NO_FLUSH } else { 0 };
+
+    unsafe { write_cr3(p4 | nf) };
+}
+
+/// Returns `true` if the kernel half was unmapped from since `seen` was 
+/// last updated, i.e. translations kept under a PCID can't be trusted.
+fn stale(seen : &AtomicUsize) -> bool {
+    let n = control::kernel_unmaps();
+    seen.swap(n, Ordering::Relaxed) != n
+}
+
+/// Page table hierarchy of a process. The kernel half is shared with 
+/// every other address space, the user half is freed on drop.
+pub struct AddressSpace {
+    p4   : Frame,
+    pcid : Option<u16>,
+    /// set until the next activation if translations tagged with 
+    /// the PCID may be stale, e.g. it was recycled or the tables 
+    /// were write protected by a fork
+    fresh : AtomicBool,
+    /// `control::kernel_unmaps` at the last flush
+    seen  : AtomicUsize,
+}
+
+impl AddressSpace {
+    fn wrap(t : InactivePTable) -> AddressSpace {
+        AddressSpace { 
+            p4    : t.p4_frame, 
+            pcid  : alloc_pcid(), 
+            fresh : AtomicBool::new(true),
+            seen  : AtomicUsize::new(control::kernel_unmaps()),
+        }
+    }
+
+    pub fn new() -> Option<AddressSpace> {
+        let t = mem::controller().new_address_space()?;
+        Some(AddressSpace::wrap(t))
+    }
+
+    /// Returns a copy-on-write duplicate of this address space.
+    pub fn fork(&self) -> Option<AddressSpace> {
+        // sized up front, the heap only grows into its reserve under the controller
+        let n = mem::controller().user_pages(&mut self.table());
+        let mut pages = Vec::with_capacity(n);
+
+        let t = mem::controller().fork_address_space(&mut self.table(), &mut pages)?;
+
+        // the active tables were flushed by the fork, ours will be on activation
+        if !self.is_active() { self.fresh.store(true, Ordering::Relaxed); }
+
+        Some(AddressSpace::wrap(t))
+    }
+
+    /// Returns a handle for editing the tables through `ActivePTable::with`.
+    pub fn table(&self) -> InactivePTable {
+        InactivePTable { p4_frame : self.p4.clone() }
+    }
+
+    pub fn is_active(&self) -> bool {
+        read_cr3() & 0x000F_FFFF_FFFF_F000 == self.p4.addr_ptr() as u64
+    }
+
+    /// Loads the tables into CR3. With PCIDs the translations cached 
+    /// for this address space survive switching away and back.
+    pub fn activate(&self) {
+        if self.is_active() { return; }
+
+        let mut cr3 = self.p4.addr_ptr() as u64;
+
+        match self.pcid {
+            Some(id) => {
+                cr3 |= id as u64;
+                let fresh = self.fresh.swap(false, Ordering::Relaxed);
+                if !(stale(&self.seen) | fresh) { cr3 |= CR3_NO_FLUSH; }
+            }
+            // runs on the kernel's PCID, which has to be flushed when 
+            // the kernel address space is loaded again
+            None => KERNEL_DIRTY.store(true, Ordering::Relaxed),
+        }
+
+        unsafe { write_cr3(cr3) };
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() { activate_kernel(); }
+
+        mem::controller().free_address_space(self.table());
+
+        if let Some(id) = self.pcid { free_pcid(id); }
+    }
+}
@@ -0,0 +1,102 @@
+// -*- mode: rust; -*-
+
+//! # Local APIC
+//!
+//! Only what's needed to start the other processors and to send and 
+//! acknowledge interrupts between them. The registers are used through 
+//! an uncached `ioremap` of the physical base from `IA32_APIC_BASE`.
+
+use core::{ ptr, sync::atomic::{ AtomicUsize, Ordering } };
+
+use x86_64::registers::msr::rdmsr;
+
+use kernel::mem::{
+    control::MemoryController,
+    globals::PAGE_SIZE,
+    paging::cache::CacheMode,
+};
+
+const IA32_APIC_BASE : u32 = 0x1B;
+
+const REG_ID    : usize = 0x020;
+const REG_EOI   : usize = 0x0B0;
+const REG_SVR   : usize = 0x0F0;
+const REG_ESR   : usize = 0x280;
+const REG_ICR_L : usize = 0x300;
+const REG_ICR_H : usize = 0x310;
+
+/// Software enable bit of the spurious interrupt vector register.
+const SVR_ENABLE : u32 = 1 << 8;
+
+pub const SPURIOUS_VECTOR : u8 = 0xFF;
+
+const ICR_INIT     : u32 = 0b101 << 8;
+const ICR_STARTUP  : u32 = 0b110 << 8;
+const ICR_ASSERT   : u32 = 1 << 14;
+const ICR_PENDING  : u32 = 1 << 12;
+
+/// Address of the registers, 0 until `init`.
+static BASE : AtomicUsize = AtomicUsize::new(0);
+
+unsafe fn read(reg : usize) -> u32 {
+    ptr::read_volatile((BASE.load(Ordering::Relaxed) + reg) as *const u32)
+}
+
+unsafe fn write(reg : usize, v : u32) {
+    ptr::write_volatile((BASE.load(Ordering::Relaxed) + reg) as *mut u32, v)
+}
+
+/// Maps the registers, uncached. Every processor has its own 
+/// registers at the same physical address.
+pub fn init(mc : &mut MemoryController) {
+    let phys = unsafe { rdmsr(IA32_APIC_BASE) } as usize & 0x000F_FFFF_FFFF_F000;
+
+    let base = mc.ioremap(phys, PAGE_SIZE, CacheMode::Uncached).expect("local APIC cannot be mapped");
+    BASE.store(base, Ordering::SeqCst);
+}
+
+/// Returns `true` once the registers are mapped.
+pub fn ready() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Enables the local APIC of the calling processor.
+pub fn enable() {
+    unsafe { write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32) };
+}
+
+/// APIC id of the calling processor.
+pub fn id() -> u32 {
+    unsafe { read(REG_ID) >> 24 }
+}
+
+pub fn eoi() {
+    unsafe { write(REG_EOI, 0) };
+}
+
+/// Sends `icr` to the processor `dest` and waits until it's delivered.
+fn send(dest : u32, icr : u32) {
+    unsafe {
+        write(REG_ESR, 0);
+        write(REG_ICR_H, dest << 24);
+        write(REG_ICR_L, icr);
+
+        while read(REG_ICR_L) & ICR_PENDING != 0 {
+            asm!("pause" :::: "volatile");
+        }
+    }
+}
+
+pub fn send_init(dest : u32) {
+    send(dest, ICR_INIT | ICR_ASSERT);
+}
+
+/// Starts `dest` in real mode at `page * 0x1000`.
+pub fn send_startup(dest : u32, page : u8) {
+    send(dest, ICR_STARTUP | ICR_ASSERT | page as u32);
+}
+
+/// Sends the fixed interrupt `vector` to `dest`.
+pub fn send_ipi(dest : u32, vector : u8) {
+    send(dest, ICR_ASSERT | vector as u32);
+}
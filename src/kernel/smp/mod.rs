@@ -0,0 +1,210 @@
+// -*- mode: rust; -*-
+
+//! # Multiprocessor startup
+//!
+//! The bootstrap processor wakes the application processors with the 
+//! INIT-SIPI-SIPI sequence. They start in real mode in the trampoline 
+//! of `ap.S`, copied below 1 MiB, reach long mode on the kernel page 
+//! tables and continue in `ap_main` on a stack prepared for them.
+//!
+//! There is no MADT parser yet, so APIC ids are probed in order until
+//! one doesn't answer, which matches how QEMU numbers its CPUs.
+
+use core::{ ptr
+          , sync::atomic::{ AtomicUsize
+                          , Ordering } };
+
+use kernel::{
+    cpu,
+    fpu,
+    interrupt::{ self
+               , pit },
+    mem::{ self
+         , alloc::{ frame::Frame
+                  , stack::{ Stack
+                           , OwnedStack } }
+         , globals::{ PhysicalAddress
+                    , PAGE_SIZE }
+         , paging::cache
+         , paging::entry::{ EFlags
+                          , PRESENT
+                          , WRITABLE
+                          , NO_EXECUTE } },
+};
+
+pub mod apic;
+pub mod percpu;
+pub mod tlb;
+
+/// Highest number of processors brought up, `percpu!` 
+/// repeats its initializer this many times.
+pub const MAX_CPUS : usize = 8;
+
+/// Where the trampoline is copied to, `AP_BASE` in `ap.S`.
+const TRAMPOLINE : PhysicalAddress = 0x8000;
+
+const AP_STACK_PAGES : usize = 4;
+
+/// How long to wait for a started processor to check in.
+const AP_TIMEOUT_MS : usize = 100;
+
+extern "C" {
+    static ap_trampoline_start : u8;
+    static ap_trampoline_end   : u8;
+    static mut ap_cr3   : u64;
+    static mut ap_stack : u64;
+    static mut ap_entry : u64;
+    static mut ap_cpu   : u64;
+}
+
+/// Number of processors which finished `ap_main` initialization, the BSP included.
+static ONLINE : AtomicUsize = AtomicUsize::new(1);
+
+const NO_APIC : usize = !0;
+
+/// APIC ids of the processors by CPU number.
+static APIC_IDS : [AtomicUsize ; MAX_CPUS] = [
+    AtomicUsize::new(0),       AtomicUsize::new(NO_APIC), AtomicUsize::new(NO_APIC), AtomicUsize::new(NO_APIC),
+    AtomicUsize::new(NO_APIC), AtomicUsize::new(NO_APIC), AtomicUsize::new(NO_APIC), AtomicUsize::new(NO_APIC),
+];
+
+/// Number of processors running.
+pub fn online() -> usize {
+    ONLINE.load(Ordering::SeqCst)
+}
+
+/// Number of the calling processor, 0 is the bootstrap processor.
+pub fn cpu_id() -> usize {
+    if percpu::ready() { return percpu::id(); }
+    if !apic::ready() { return 0; }
+
+    let id = apic::id() as usize;
+    APIC_IDS.iter().position(|a| a.load(Ordering::Relaxed) == id).unwrap_or(0)
+}
+
+/// APIC id of the processor `cpu`, if it was started.
+pub fn apic_id(cpu : usize) -> Option<u32> {
+    match APIC_IDS.get(cpu).map(|a| a.load(Ordering::Relaxed)) {
+        Some(NO_APIC) | None => None,
+        Some(id)             => Some(id as u32),
+    }
+}
+
+fn wait_ms(ms : usize) {
+    let end = pit::ticks() + pit::ms_to_ticks(ms) + 1;
+    while pit::ticks() < end {
+        unsafe { asm!("pause" :::: "volatile") };
+    }
+}
+
+/// Address of the trampoline symbol `sym` in the copy at `TRAMPOLINE`.
+unsafe fn relocated<T>(sym : *const T) -> *mut T {
+    (TRAMPOLINE + (sym as usize - &ap_trampoline_start as *const u8 as usize)) as *mut T
+}
+
+/// Starts the application processors. Needs interrupts enabled 
+/// as the delays of the startup sequence are timed by the PIT.
+pub fn init() {
+    once!("smp::init cannot be called twice");
+
+    if !cpu::has(cpu::APIC) {
+        println!("smp: no local APIC, running on one cpu");
+        return;
+    }
+
+    let cr3 = {
+        let mut mc = mem::controller();
+        apic::init(&mut mc);
+        mc.identity_map(&Frame::caddr(TRAMPOLINE), WRITABLE | NO_EXECUTE);
+        mc.active_p4().addr_ptr() as u64
+    };
+
+    // the trampoline loads CR3 in protected mode
+    assert!(cr3 < 0x1_0000_0000, "kernel P4 table above 4 GiB");
+
+    unsafe {
+        let start = &ap_trampoline_start as *const u8;
+        let len   = &ap_trampoline_end as *const u8 as usize - start as usize;
+
+        ptr::copy_nonoverlapping(start, TRAMPOLINE as *mut u8, len);
+
+        *relocated(&ap_cr3)   = cr3;
+        *relocated(&ap_entry) = ap_main as u64;
+    }
+
+    apic::enable();
+
+    let me = apic::id();
+    APIC_IDS[0].store(me as usize, Ordering::SeqCst);
+
+    for id in (0..MAX_CPUS as u32).filter(|&id| id != me) {
+        // kept even if the processor doesn't answer, it might just be late
+        let st = match OwnedStack::alloc(AP_STACK_PAGES) {
+            Some(st) => st.leak(),
+            None     => break,
+        };
+
+        // a processor which timed out may still use the trampoline,
+        // it's never rewritten after that
+        if !start(id, &st) || online() == MAX_CPUS { break; }
+    }
+
+    println!("smp: {} cpus online", online());
+}
+
+/// Sets the flags of the trampoline page, it's never writable 
+/// and executable at once.
+fn protect_trampoline(fl : EFlags) {
+    mem::controller().protect(TRAMPOLINE, PAGE_SIZE, fl);
+}
+
+/// Runs INIT-SIPI-SIPI on the processor `id` and waits until it's online.
+/// After a timeout it must not be called again: the processor may 
+/// still run from the trampoline, which is left executable.
+fn start(id : u32, st : &Stack) -> bool {
+    let cpu = online();
+
+    protect_trampoline(WRITABLE | NO_EXECUTE);
+
+    unsafe {
+        *relocated(&ap_stack) = st.top() as u64;
+        *relocated(&ap_cpu)   = cpu as u64;
+    }
+
+    protect_trampoline(PRESENT);
+
+    APIC_IDS[cpu].store(id as usize, Ordering::SeqCst);
+
+    apic::send_init(id);
+    wait_ms(10);
+
+    for _ in 0..2 {
+        apic::send_startup(id, (TRAMPOLINE >> 12) as u8);
+        wait_ms(1);
+        if online() > cpu { return true; }
+    }
+
+    wait_ms(AP_TIMEOUT_MS);
+
+    if online() > cpu { return true; }
+
+    APIC_IDS[cpu].store(NO_APIC, Ordering::SeqCst);
+    false
+}
+
+/// First Rust code run by an application processor, on the stack 
+/// given to it by `start`.
+extern "C" fn ap_main(cpu : usize) -> ! {
+    cache::init();
+    fpu::init();
+    interrupt::init_ap(cpu, &mut mem::controller());
+    apic::enable();
+
+    ONLINE.fetch_add(1, Ordering::SeqCst);
+
+    unsafe { interrupt::enable() };
+
+    loop {
+        unsafe { asm!("hlt" :::: "volatile") };
+    }
+}
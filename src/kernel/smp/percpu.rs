@@ -0,0 +1,202 @@
+// -*- mode: rust; -*-
+
+//! # Per-CPU data
+//!
+//! Every processor has a `Cpu` block whose address is in its GS base
+//! while running kernel code. On the way to ring 3 `swapgs` parks it in
+//! `IA32_KERNEL_GS_BASE`, entries from ring 3 swap it back: `syscall_entry`
+//! and interrupt handlers through `UserEntry`.
+//!
+//! Variables declared with `percpu!` live in `PerCpu` arrays
+//! indexed by the number of the running processor.
+
+use core::{ cell::UnsafeCell, ptr };
+
+use x86_64::{
+    VirtualAddress,
+    structures::{ idt::ExceptionStackFrame
+                , tss::TaskStateSegment },
+    registers::msr::{ IA32_GS_BASE
+                    , IA32_KERNEL_GS_BASE
+                    , rdmsr
+                    , wrmsr },
+};
+
+use kernel::{
+    interrupt::{ self
+               , gdt },
+};
+
+use super::MAX_CPUS;
+
+/// Offsets used by `syscall_entry`, keep in sync with `syscall.S`.
+pub const CPU_KERNEL_RSP : usize = 8;
+pub const CPU_USER_RSP   : usize = 16;
+/// Offset of `Cpu::id`, read by `id`.
+pub const CPU_ID         : usize = 24;
+
+#[repr(C)]
+pub struct Cpu {
+    /// address of the block itself, so it can be found from `gs:0`
+    this       : usize,
+    /// stack `syscall_entry` switches to
+    kernel_rsp : usize,
+    /// user stack pointer saved by `syscall_entry`
+    user_rsp   : usize,
+    pub id      : usize,
+    /// id of the thread running on this processor
+    thread     : usize,
+    /// preemption is allowed only while it's 0
+    preempt    : usize,
+    /// top of a small stack for when no thread stack is known, 
+    /// e.g. system calls made by the boot thread
+    pub scratch : usize,
+    pub tss     : TaskStateSegment,
+    pub gdt     : gdt::GDT,
+}
+
+static mut CPUS : [Option<Cpu> ; MAX_CPUS] = [None, None, None, None, None, None, None, None];
+
+/// Creates the block of the processor `id` with an empty GDT and loads 
+/// it into the GS base, the caller fills the GDT through `current`.
+pub fn init(id : usize, scratch : usize, tss : TaskStateSegment) {
+    unsafe {
+        assert!(CPUS[id].is_none(), "CPU {} is initialized twice", id);
+
+        CPUS[id] = Some(Cpu { 
+            this : 0, kernel_rsp : scratch, user_rsp : 0, thread : 0, preempt : 0, 
+            gdt  : gdt::GDT::new(),
+            id, scratch, tss,
+        });
+
+        let cpu = CPUS[id].as_mut().unwrap();
+        cpu.this = cpu as *mut Cpu as usize;
+
+        wrmsr(IA32_GS_BASE, cpu.this as u64);
+        wrmsr(IA32_KERNEL_GS_BASE, 0);
+    }
+}
+
+/// Returns `true` once the calling processor has its block.
+pub fn ready() -> bool {
+    unsafe { rdmsr(IA32_GS_BASE) != 0 }
+}
+
+/// Block of the calling processor.
+pub fn current() -> &'static mut Cpu {
+    let p : usize;
+    unsafe { 
+        asm!("mov $0, qword ptr gs:[0]" : "=r"(p) ::: "intel", "volatile");
+        &mut *(p as *mut Cpu)
+    }
+}
+
+/// Number of the calling processor.
+pub fn id() -> usize {
+    let id : usize;
+    unsafe { asm!("mov $0, qword ptr gs:[$1]" : "=r"(id) : "i"(CPU_ID) :: "intel", "volatile") };
+    id
+}
+
+/// Sets the stack used on entries from ring 3, by interrupts and system calls.
+pub fn set_kernel_stack(top : usize) {
+    let cpu = current();
+    cpu.tss.privilege_stack_table[0] = VirtualAddress(top);
+    cpu.kernel_rsp = top;
+}
+
+pub fn set_thread(id : usize) {
+    current().thread = id;
+}
+
+/// Id of the thread running on the calling processor.
+pub fn thread() -> usize {
+    current().thread
+}
+
+pub fn preempt_disable() {
+    let e = interrupt::save_disable();
+    current().preempt += 1;
+    interrupt::restore(e);
+}
+
+pub fn preempt_enable() {
+    let e = interrupt::save_disable();
+    let cpu = current();
+    assert!(cpu.preempt > 0, "unbalanced preempt_enable");
+    cpu.preempt -= 1;
+    interrupt::restore(e);
+}
+
+/// Returns `true` if the running thread may be preempted.
+pub fn preemptible() -> bool {
+    current().preempt == 0
+}
+
+/// Switches to the kernel GS base for an interrupt which came from 
+/// ring 3 and back once dropped, i.e. right before the `iretq`.
+pub struct UserEntry(bool);
+
+impl UserEntry {
+    pub fn new(f : &ExceptionStackFrame) -> UserEntry {
+        UserEntry::swap(f.code_segment & 3 == 3)
+    }
+
+    /// For NMIs and machine checks, which also hit the ring 0 code 
+    /// around `swapgs` in `syscall_entry` and `jump_to_user` while GS 
+    /// still holds the user base: swaps unless the GS base already 
+    /// points to a `Cpu` block.
+    pub fn paranoid() -> UserEntry {
+        let gs = unsafe { rdmsr(IA32_GS_BASE) } as usize;
+        UserEntry::swap(!is_block(gs))
+    }
+
+    fn swap(user : bool) -> UserEntry {
+        if user { unsafe { asm!("swapgs" :::: "volatile") }; }
+        UserEntry(user)
+    }
+}
+
+/// Returns `true` if `addr` is the block of a processor.
+fn is_block(addr : usize) -> bool {
+    unsafe { CPUS.iter().any(|c| c.as_ref().map_or(false, |c| c.this == addr)) }
+}
+
+impl Drop for UserEntry {
+    fn drop(&mut self) {
+        if self.0 { unsafe { asm!("swapgs" :::: "volatile") }; }
+    }
+}
+
+/// Storage of a variable declared with `percpu!`, one value per processor.
+pub struct PerCpu<T> {
+    v : UnsafeCell<[T ; MAX_CPUS]>,
+}
+
+unsafe impl<T : Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    pub const fn new(v : [T ; MAX_CPUS]) -> PerCpu<T> {
+        PerCpu { v : UnsafeCell::new(v) }
+    }
+
+    /// Runs `f` on the value of the calling processor with interrupts 
+    /// disabled, so nothing else on this processor can get at it.
+    pub fn with<F, R>(&self, f : F) -> R
+    where
+        F : FnOnce(&mut T) -> R
+    {
+        let e = interrupt::save_disable();
+        let r = f(unsafe { &mut (*self.v.get())[super::cpu_id()] });
+        interrupt::restore(e);
+        r
+    }
+
+    /// Reads the value of the processor `cpu`.
+    pub fn get(&self, cpu : usize) -> T 
+    where 
+        T : Copy
+    {
+        unsafe { ptr::read_volatile(&(*self.v.get())[cpu]) }
+    }
+}
@@ -0,0 +1,181 @@
+// -*- mode: rust; -*-
+
+//! # TLB shootdown
+//!
+//! `invlpg` only drops translations cached by the processor running it.
+//! Code changing mappings collects the pages in a `Shootdown`, which 
+//! flushes them locally once dropped and makes the other processors 
+//! which may cache them do the same through `TLB_VECTOR`: all of them 
+//! for the shared kernel half, for the user half only those running 
+//! on the same P4 table. The initiator waits for every target to 
+//! acknowledge before going on.
+//!
+//! Translations tagged with the PCID of an address space a processor 
+//! switched away from aren't reached. Only the bootstrap processor 
+//! runs threads so far, it flushes those on activation.
+
+use core::sync::atomic::{ AtomicBool
+                        , AtomicUsize
+                        , Ordering };
+
+use x86_64::{ VirtualAddress
+            , instructions::tlb };
+
+use kernel::{
+    interrupt,
+    mem::{ control
+         , paging::page::Page },
+};
+
+use super::{ apic
+           , percpu
+           , online
+           , cpu_id
+           , apic_id
+           , MAX_CPUS };
+
+/// Interrupt asking a processor to serve the pending shootdown.
+pub const TLB_VECTOR : u8 = 0xFD;
+
+/// Pages a shootdown carries, beyond that whole TLBs are flushed.
+const MAX_PAGES : usize = 16;
+
+percpu! {
+    /// Address of the P4 table loaded by each processor, 0 if unknown.
+    static ACTIVE_P4 : usize = 0;
+}
+
+#[derive(Clone, Copy)]
+struct Request {
+    pages : [usize ; MAX_PAGES],
+    /// number of pages, more than `MAX_PAGES` means everything
+    n     : usize,
+}
+
+impl Request {
+    fn invalidate(&self) {
+        if self.n > MAX_PAGES { 
+            tlb::flush_all(); 
+        } else {
+            self.pages[..self.n].iter().for_each(|&a| tlb::flush(VirtualAddress(a)));
+        }
+    }
+}
+
+/// Set while a shootdown is in flight, one at a time.
+static BUSY : AtomicBool = AtomicBool::new(false);
+
+/// The shootdown in flight, written only by the owner of `BUSY`.
+static mut REQUEST : Request = Request { pages : [0 ; MAX_PAGES], n : 0 };
+
+/// Processors which didn't acknowledge `REQUEST` yet, one bit each.
+static PENDING : AtomicUsize = AtomicUsize::new(0);
+
+/// Serves `REQUEST` if the calling processor is one of its targets.
+/// Code spinning with interrupts disabled calls it, the initiator 
+/// may be waiting for it while holding what it spins on.
+pub fn serve() {
+    let bit = 1 << cpu_id();
+
+    if PENDING.load(Ordering::SeqCst) & bit != 0 {
+        unsafe { REQUEST.invalidate() };
+        PENDING.fetch_and(!bit, Ordering::SeqCst);
+    }
+}
+
+/// Called from the `TLB_VECTOR` handler.
+pub fn handle() {
+    serve();
+    apic::eoi();
+}
+
+/// Records the P4 table the calling processor just loaded.
+pub fn set_active(p4 : usize) {
+    ACTIVE_P4.with(|a| *a = p4);
+}
+
+/// Batch of pages whose translations have to go, flushed on drop.
+pub struct Shootdown {
+    r      : Request,
+    /// some pages are in the kernel half
+    kernel : bool,
+}
+
+impl Shootdown {
+    pub fn new() -> Shootdown {
+        Shootdown { r : Request { pages : [0 ; MAX_PAGES], n : 0 }, kernel : false }
+    }
+
+    /// Adds the page containing `addr`.
+    pub fn add(&mut self, addr : usize) {
+        if control::is_kernel_p4(Page::caddr(addr).p4_idx()) { self.kernel = true; }
+
+        if self.r.n < MAX_PAGES { self.r.pages[self.r.n] = addr; }
+        if self.r.n <= MAX_PAGES { self.r.n += 1; }
+    }
+
+    /// Flushes the user half of the address space, e.g. after 
+    /// many of its pages changed.
+    pub fn add_user(&mut self) {
+        self.r.n = MAX_PAGES + 1;
+    }
+
+    /// Processors other than the caller which may cache the pages.
+    fn targets(&self) -> usize {
+        let me = cpu_id();
+        let p4 = ACTIVE_P4.get(me);
+
+        (0..online())
+            .filter(|&c| c != me)
+            .filter(|&c| self.kernel || { let q = ACTIVE_P4.get(c); q == 0 || q == p4 })
+            .fold(0, |m, c| m | 1 << c)
+    }
+}
+
+impl Drop for Shootdown {
+    fn drop(&mut self) {
+        if self.r.n == 0 { return; }
+
+        self.r.invalidate();
+
+        if online() == 1 || !percpu::ready() { return; }
+
+        let targets = self.targets();
+        if targets == 0 { return; }
+
+        let e = interrupt::save_disable();
+
+        // whoever owns `BUSY` may be waiting for us
+        while BUSY.compare_and_swap(false, true, Ordering::Acquire) {
+            serve();
+            unsafe { asm!("pause" :::: "volatile") };
+        }
+
+        unsafe { REQUEST = self.r };
+        PENDING.store(targets, Ordering::SeqCst);
+
+        (0..MAX_CPUS)
+            .filter(|&c| targets & 1 << c != 0)
+            .filter_map(apic_id)
+            .for_each(|id| apic::send_ipi(id, TLB_VECTOR));
+
+        while PENDING.load(Ordering::SeqCst) != 0 {
+            unsafe { asm!("pause" :::: "volatile") };
+        }
+
+        BUSY.store(false, Ordering::Release);
+        interrupt::restore(e);
+    }
+}
+
+/// Flushes the page containing `addr` on every processor which may cache it.
+pub fn flush(addr : usize) {
+    let mut sd = Shootdown::new();
+    sd.add(addr);
+}
+
+/// Flushes the user half of the active address space everywhere it's loaded.
+pub fn flush_user() {
+    let mut sd = Shootdown::new();
+    sd.add_user();
+}
@@ -0,0 +1,49 @@
+// -*- mode: rust; -*-
+
+use core::mem;
+
+use kernel::interrupt;
+
+use super::{ WaitQueue
+           , MutexGuard };
+
+/// Condition variable used together with a sleeping `Mutex`.
+pub struct Condvar {
+    q : WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar { q : WaitQueue::new() }
+    }
+
+    /// Releases the mutex and sleeps until notified, then takes the mutex again.
+    pub fn wait<'a, T>(&self, g : MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let m = g.mutex();
+
+        // nothing can notify between unlocking and going to sleep
+        let e = interrupt::save_disable();
+        mem::drop(g);
+        self.q.wait();
+        interrupt::restore(e);
+
+        m.lock()
+    }
+
+    /// Sleeps as long as `cond` holds for the protected value.
+    pub fn wait_while<'a, T, F>(&self, mut g : MutexGuard<'a, T>, mut cond : F) -> MutexGuard<'a, T>
+    where
+        F : FnMut(&mut T) -> bool
+    {
+        while cond(&mut *g) { g = self.wait(g); }
+        g
+    }
+
+    pub fn notify_one(&self) {
+        self.q.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.q.wake_all();
+    }
+}
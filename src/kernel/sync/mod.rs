@@ -0,0 +1,21 @@
+// -*- mode: rust; -*-
+
+//! # Synchronization primitives
+//!
+//! `SpinLock` is the only primitive usable from interrupt handlers,
+//! everything else puts the calling thread to sleep while waiting.
+
+mod spinlock;
+pub use self::spinlock::{ SpinLock, SpinLockGuard };
+
+mod waitqueue;
+pub use self::waitqueue::WaitQueue;
+
+mod mutex;
+pub use self::mutex::{ Mutex, MutexGuard };
+
+mod semaphore;
+pub use self::semaphore::Semaphore;
+
+mod condvar;
+pub use self::condvar::Condvar;
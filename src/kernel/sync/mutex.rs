@@ -0,0 +1,74 @@
+// -*- mode: rust; -*-
+
+use core::{
+    cell::UnsafeCell,
+    ops::{ Deref, DerefMut },
+    sync::atomic::{ AtomicBool, Ordering },
+};
+
+use super::WaitQueue;
+
+/// Mutual exclusion lock which puts contending threads to sleep.
+/// Must not be used from interrupt handlers.
+pub struct Mutex<T> {
+    l : AtomicBool,
+    q : WaitQueue,
+    v : UnsafeCell<T>,
+}
+
+unsafe impl<T : Send> Sync for Mutex<T> {}
+unsafe impl<T : Send> Send for Mutex<T> {}
+
+pub struct MutexGuard<'a, T : 'a> {
+    m : &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(v : T) -> Mutex<T> {
+        Mutex { l : AtomicBool::new(false), q : WaitQueue::new(), v : UnsafeCell::new(v) }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if self.l.swap(true, Ordering::Acquire) { None } 
+        else { Some(MutexGuard { m : self }) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<T> {
+        loop {
+            if let Some(g) = self.try_lock() { return g; }
+            self.q.wait_while(|| self.l.load(Ordering::Relaxed));
+        }
+    }
+
+    fn unlock(&self) {
+        self.l.store(false, Ordering::Release);
+        self.q.wake_one();
+    }
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// Returns the mutex this guard belongs to.
+    pub fn mutex(&self) -> &'a Mutex<T> {
+        self.m
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.m.v.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.m.v.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.m.unlock();
+    }
+}
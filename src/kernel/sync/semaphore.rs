@@ -0,0 +1,45 @@
+// -*- mode: rust; -*-
+
+use core::sync::atomic::{ AtomicUsize, Ordering };
+
+use super::WaitQueue;
+
+/// Counting semaphore, `down` sleeps while the count is zero.
+pub struct Semaphore {
+    c : AtomicUsize,
+    q : WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(c : usize) -> Semaphore {
+        Semaphore { c : AtomicUsize::new(c), q : WaitQueue::new() }
+    }
+
+    /// Takes a unit without sleeping, returns `false` if there was none.
+    pub fn try_down(&self) -> bool {
+        let mut c = self.c.load(Ordering::Relaxed);
+
+        while c > 0 {
+            match self.c.compare_exchange_weak(c, c - 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_)  => return true,
+                Err(n) => c = n,
+            }
+        }
+        false
+    }
+
+    pub fn down(&self) {
+        while !self.try_down() {
+            self.q.wait_while(|| self.c.load(Ordering::Relaxed) == 0);
+        }
+    }
+
+    pub fn up(&self) {
+        self.c.fetch_add(1, Ordering::Release);
+        self.q.wake_one();
+    }
+
+    pub fn count(&self) -> usize {
+        self.c.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,96 @@
+// -*- mode: rust; -*-
+
+use spin;
+
+use core::{ ops::{ Deref, DerefMut }
+           , sync::atomic::{ AtomicUsize, Ordering } };
+
+use kernel::{ interrupt, smp::{ self, tlb } };
+
+/// Owner of a free lock.
+const NO_OWNER : usize = !0;
+
+/// Spin lock which keeps interrupts disabled while held, so an 
+/// interrupt handler can never spin on a lock owned by the code 
+/// it interrupted.
+pub struct SpinLock<T> {
+    l : spin::Mutex<T>,
+    /// processor holding the lock
+    o : AtomicUsize,
+}
+
+pub struct SpinLockGuard<'a, T : 'a> {
+    g : Option<spin::MutexGuard<'a, T>>,
+    /// whether interrupts were enabled before locking
+    e : bool,
+    o : &'a AtomicUsize,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(v : T) -> SpinLock<T> {
+        SpinLock { l : spin::Mutex::new(v), o : AtomicUsize::new(NO_OWNER) }
+    }
+
+    /// Spins until the lock is free. The caller may have interrupts 
+    /// disabled, e.g. it holds another lock, so pending TLB shootdowns 
+    /// are served while waiting: the owner may be waiting for them.
+    pub fn lock(&self) -> SpinLockGuard<T> {
+        loop {
+            if let Some(g) = self.try_lock() { return g; }
+            tlb::serve();
+            unsafe { asm!("pause" :::: "volatile") };
+        }
+    }
+
+    /// Like `lock`, but returns `None` instead of spinning forever if 
+    /// this processor holds the lock already, e.g. when code running 
+    /// under the lock faults.
+    pub fn lock_unless_held(&self) -> Option<SpinLockGuard<T>> {
+        if self.held() { None } else { Some(self.lock()) }
+    }
+
+    /// Returns `true` if this processor holds the lock.
+    pub fn held(&self) -> bool {
+        // only this processor can store its own id, and it doesn't 
+        // while the check runs with interrupts disabled
+        let e = interrupt::save_disable();
+        let held = self.o.load(Ordering::Relaxed) == smp::cpu_id();
+        interrupt::restore(e);
+        held
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<T>> {
+        let e = interrupt::save_disable();
+
+        match self.l.try_lock() {
+            Some(g) => {
+                self.o.store(smp::cpu_id(), Ordering::Relaxed);
+                Some(SpinLockGuard { g : Some(g), e, o : &self.o })
+            }
+            None    => { interrupt::restore(e); None }
+        }
+    }
+}
+
+impl<'a, T> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.g.as_ref().unwrap()
+    }
+}
+
+impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.g.as_mut().unwrap()
+    }
+}
+
+impl<'a, T> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        // release the lock before interrupts may come back
+        self.o.store(NO_OWNER, Ordering::Relaxed);
+        self.g.take();
+        interrupt::restore(self.e);
+    }
+}
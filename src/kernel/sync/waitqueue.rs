@@ -0,0 +1,64 @@
+// -*- mode: rust; -*-
+
+use alloc::VecDeque;
+
+use kernel::{ interrupt, thread };
+
+use super::SpinLock;
+
+/// Queue of threads sleeping until an event happens.
+pub struct WaitQueue {
+    q : SpinLock<Option<VecDeque<usize>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue { q : SpinLock::new(None) }
+    }
+
+    fn push(&self, id : usize) {
+        self.q.lock().get_or_insert_with(VecDeque::new).push_back(id);
+    }
+
+    fn pop(&self) -> Option<usize> {
+        self.q.lock().as_mut().and_then(|q| q.pop_front())
+    }
+
+    /// Sleeps as long as `cond` holds. The condition is checked with 
+    /// interrupts disabled right before going to sleep, so a wake up 
+    /// issued after it turned false can't be missed.
+    pub fn wait_while<F>(&self, cond : F) 
+    where
+        F : Fn() -> bool
+    {
+        let e = interrupt::save_disable();
+
+        while cond() {
+            self.push(thread::current());
+            thread::block();
+        }
+
+        interrupt::restore(e);
+    }
+
+    /// Sleeps until woken up by `wake_one` or `wake_all`.
+    pub fn wait(&self) {
+        let e = interrupt::save_disable();
+        self.push(thread::current());
+        thread::block();
+        interrupt::restore(e);
+    }
+
+    /// Wakes up the longest waiting thread, returns `false` if there was none.
+    pub fn wake_one(&self) -> bool {
+        while let Some(id) = self.pop() {
+            if thread::unblock(id) { return true; }
+        }
+        false
+    }
+
+    /// Wakes up every waiting thread.
+    pub fn wake_all(&self) {
+        while self.wake_one() {}
+    }
+}
@@ -0,0 +1,139 @@
+// -*- mode: rust; -*-
+
+use core::{ cmp, ptr };
+
+use x86_64::structures::idt::PageFaultErrorCode;
+
+use kernel::{
+    thread,
+    vga,
+    sync::SpinLock,
+    process::{ MMAP_BASE
+             , MMAP_END },
+    interrupt::fault::{ self
+                      , Fault
+                      , Outcome },
+    mem::{ self
+         , globals::PAGE_SIZE
+         , alloc::heap::{ align_up
+                        , align_down }
+         , paging::entry::{ WRITABLE
+                          , NO_EXECUTE
+                          , USER_ACCESSIBLE } },
+};
+
+use super::{ Error, check_user };
+
+const STDOUT : usize = 1;
+const STDERR : usize = 2;
+
+/// User buffers are copied through the kernel stack in chunks of this size.
+const CHUNK : usize = 256;
+
+pub const PROT_WRITE : usize = 0x2;
+pub const PROT_EXEC  : usize = 0x4;
+
+/// `mmap` cursor of code running in ring 3 without a process,
+/// processes keep their own.
+static MMAP_NEXT : SpinLock<usize> = SpinLock::new(MMAP_BASE);
+
+/// exit(code)
+pub fn exit(_a : &[usize ; 6]) -> Result<usize, Error> {
+    thread::exit()
+}
+
+/// write(fd, buf, len)
+pub fn write(a : &[usize ; 6]) -> Result<usize, Error> {
+    let (fd, buf, len) = (a[0], a[1], a[2]);
+
+    if fd != STDOUT && fd != STDERR { return Err(Error::BadFd); }
+    check_user(buf, len)?;
+    fault_in(buf, len)?;
+
+    let mut chunk = [0u8 ; CHUNK];
+    let mut done  = 0;
+
+    while done < len {
+        let n = cmp::min(CHUNK, len - done);
+        unsafe { ptr::copy_nonoverlapping((buf + done) as *const u8, chunk.as_mut_ptr(), n) };
+
+        let mut w = vga::WRITER.lock();
+        chunk[..n].iter().for_each(|&b| w.write_byte(b));
+        done += n;
+    }
+
+    Ok(len)
+}
+
+/// Makes sure the user range `[addr, addr + len)` can be read by the 
+/// kernel, pages which aren't backed yet are faulted in the way a user 
+/// access would. Fails with `Error::Fault` on pages the process can't use.
+fn fault_in(addr : usize, len : usize) -> Result<(), Error> {
+    let mut p = align_down(addr, PAGE_SIZE);
+
+    while p < addr + len {
+        // the controller must be unlocked when the resolvers run
+        let fl = mem::controller().flags(p);
+
+        let fl = match fl {
+            Some(fl) => Some(fl),
+            None     => {
+                let f = Fault { addr : p, code : PageFaultErrorCode::USER_MODE, ip : 0 };
+                if fault::resolve(&f) != Outcome::Resolved { return Err(Error::Fault); }
+                mem::controller().flags(p)
+            }
+        };
+
+        match fl {
+            Some(fl) if fl.contains(USER_ACCESSIBLE) => p += PAGE_SIZE,
+            _ => return Err(Error::Fault),
+        }
+    }
+
+    Ok(())
+}
+
+/// yield()
+pub fn yield_now(_a : &[usize ; 6]) -> Result<usize, Error> {
+    thread::yield_now();
+    Ok(0)
+}
+
+/// sleep(ms)
+pub fn sleep(a : &[usize ; 6]) -> Result<usize, Error> {
+    thread::sleep(a[0]);
+    Ok(0)
+}
+
+/// mmap(addr, len, prot), anonymous memory only, 
+/// `addr` is a hint which is currently ignored.
+/// Pages of processes are mapped on first touch.
+pub fn mmap(a : &[usize ; 6]) -> Result<usize, Error> {
+    let (len, prot) = (a[1], a[2]);
+
+    if len == 0 { return Err(Error::Invalid); }
+
+    let len = align_up(len, PAGE_SIZE);
+
+    let mut fl = USER_ACCESSIBLE;
+    if prot & PROT_WRITE != 0 { fl |= WRITABLE; }
+    if prot & PROT_EXEC  == 0 { fl |= NO_EXECUTE; }
+
+    if let Some(p) = thread::current_process() {
+        return p.add_region(len, fl).ok_or(Error::NoMemory);
+    }
+
+    // the cursor only moves past mappings which were made
+    let mut next = MMAP_NEXT.lock();
+    let addr = *next;
+
+    match addr.checked_add(len) {
+        Some(e) if e <= MMAP_END => {}
+        _ => return Err(Error::NoMemory),
+    }
+
+    if !mem::controller().map_range(addr, len, fl) { return Err(Error::NoMemory); }
+
+    *next = addr + len;
+    Ok(addr)
+}
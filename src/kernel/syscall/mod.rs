@@ -0,0 +1,116 @@
+// -*- mode: rust; -*-
+
+//! # System calls
+//!
+//! User code enters through `syscall` with the call number in `rax`
+//! and up to six arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8`, `r9`.
+//! The result comes back in `rax`, errors as negated `Error` codes.
+
+use x86_64::registers::msr::{ IA32_STAR
+                            , IA32_LSTAR
+                            , IA32_FMASK
+                            , wrmsr };
+
+use kernel::{
+    bits,
+    cpu,
+    interrupt,
+    thread,
+    mem::globals::{ USER_START
+                  , USER_END },
+};
+
+mod calls;
+
+extern "C" {
+    fn syscall_entry();
+}
+
+/// Flags cleared on entry: TF, IF, DF and AC.
+const SFMASK : u64 = 1 << 8 | 1 << 9 | 1 << 10 | 1 << 18;
+
+pub const SYS_EXIT  : usize = 0;
+pub const SYS_WRITE : usize = 1;
+pub const SYS_YIELD : usize = 2;
+pub const SYS_SLEEP : usize = 3;
+pub const SYS_MMAP  : usize = 4;
+
+type Handler = fn(&[usize ; 6]) -> Result<usize, Error>;
+
+static TABLE : [Option<Handler> ; 5] = [
+    Some(calls::exit),
+    Some(calls::write),
+    Some(calls::yield_now),
+    Some(calls::sleep),
+    Some(calls::mmap),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Error {
+    BadFd     = 9,
+    NoMemory  = 12,
+    Fault     = 14,
+    Invalid   = 22,
+    NoSys     = 38,
+}
+
+/// Registers pushed by `syscall_entry`.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Frame {
+    pub nr     : usize,
+    pub args   : [usize ; 6],
+    pub rflags : usize,
+    pub rip    : usize,
+    pub rsp    : usize,
+}
+
+/// Programs the MSRs used by `syscall` and `sysret`.
+pub fn init() {
+    let s = interrupt::selectors();
+
+    // sysret loads SS from base + 8 and CS from base + 16, 
+    // which are the user data and code segments
+    let sysret_base = (s.kernel_data.0 | 3) as u64;
+    let star = (sysret_base << 48) | ((s.kernel_code.0 as u64) << 32);
+
+    assert!(cpu::has(cpu::SYSCALL), "the CPU has no syscall instruction");
+    bits::enable_syscall_bit();
+
+    unsafe {
+        wrmsr(IA32_STAR, star);
+        wrmsr(IA32_LSTAR, syscall_entry as u64);
+        wrmsr(IA32_FMASK, SFMASK);
+    }
+}
+
+/// Checks that `[addr, addr + len)` lies in user space.
+pub fn check_user(addr : usize, len : usize) -> Result<(), Error> {
+    match addr.checked_add(len) {
+        Some(end) if addr >= USER_START && end <= USER_END => Ok(()),
+        _ => Err(Error::Fault),
+    }
+}
+
+fn is_canonical(addr : usize) -> bool {
+    addr < 0x0000_8000_0000_0000 || addr >= 0xFFFF_8000_0000_0000
+}
+
+#[no_mangle]
+pub extern "C" fn syscall_dispatch(f : &mut Frame) -> isize {
+    unsafe { interrupt::enable() };
+
+    // sysret to a non-canonical address faults in ring 0
+    if !is_canonical(f.rip) { thread::exit(); }
+
+    let r = match TABLE.get(f.nr).and_then(|h| *h) {
+        Some(h) => h(&f.args),
+        None    => Err(Error::NoSys),
+    };
+
+    match r {
+        Ok(v)  => v as isize,
+        Err(e) => -(e as isize),
+    }
+}
@@ -0,0 +1,333 @@
+// -*- mode: rust; -*-
+
+//! # Kernel threads
+//!
+//! Threads are kept in per-priority run queues and switched either 
+//! voluntarily (`yield_now`, `sleep`, `block`, `exit`) or by the timer 
+//! interrupt once their time slice is used up.
+
+use alloc::{ arc::Arc
+           , boxed::{ Box
+                    , FnBox } };
+
+use core::sync::atomic::{ AtomicUsize
+                        , ATOMIC_USIZE_INIT
+                        , Ordering };
+
+use kernel::{
+    fpu,
+    interrupt::{ self
+               , pit },
+    mem::alloc::stack::OwnedStack,
+    process::Process,
+    smp::percpu,
+};
+
+mod sched;
+use self::sched::{ SCHEDULER
+                 , Scheduler };
+
+/// Number of pages of every thread stack.
+pub const STACK_PAGES : usize = 4;
+
+/// Number of run queues.
+pub const PRIORITIES : usize = 3;
+
+/// Initial flags of a new thread, interrupts enabled.
+const INITIAL_RFLAGS : usize = 0x202;
+
+static NEXT_ID : AtomicUsize = ATOMIC_USIZE_INIT;
+
+extern "C" {
+    fn switch_context(old_rsp : *mut usize, new_rsp : usize);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Ready,
+    Running,
+    /// waiting for the given tick
+    Sleeping(usize),
+    /// waiting for `unblock`
+    Blocked,
+    Dead,
+}
+
+pub struct Thread {
+    id    : usize,
+    name  : &'static str,
+    prio  : Priority,
+    state : State,
+    /// timer ticks spent running
+    ticks : usize,
+    idle  : bool,
+    /// `unblock` came before `block`, the next `block` returns at once
+    wakeup : bool,
+    /// saved stack pointer while the thread is switched out
+    rsp   : usize,
+    /// `None` for the boot thread which runs on the boot stack,
+    /// freed by `reap` with the thread
+    stack : Option<OwnedStack>,
+    entry : Option<Box<FnBox() + Send>>,
+    /// `None` for kernel threads
+    process : Option<Arc<Process>>,
+    /// FPU and SIMD registers, loaded on first use
+    fpu   : fpu::State,
+}
+
+impl Thread {
+    /// Wraps the flow of control which is already running.
+    fn boot() -> Thread {
+        Thread { 
+            id    : NEXT_ID.fetch_add(1, Ordering::SeqCst),
+            name  : "main",
+            prio  : Priority::Normal,
+            state : State::Running,
+            ticks : 0,
+            idle  : false,
+            wakeup : false,
+            rsp   : 0,
+            stack : None,
+            entry : None,
+            process : None,
+            fpu   : fpu::State::new(),
+        }
+    }
+
+    /// Creates a thread which starts in `thread_start` on the given stack.
+    fn new(name : &'static str, prio : Priority, stack : OwnedStack, entry : Box<FnBox() + Send>) -> Thread {
+        // stack layout expected by `switch_context`: 
+        // rflags, r15, r14, r13, r12, rbx, rbp, return address, 
+        // and a dummy slot so that the entry sees an aligned stack
+        let frame : [usize ; 9] = [INITIAL_RFLAGS, 0, 0, 0, 0, 0, 0, thread_start as usize, 0];
+        let rsp = stack.top() - frame.len() * 8;
+
+        unsafe {
+            ::core::ptr::copy_nonoverlapping(frame.as_ptr(), rsp as *mut usize, frame.len());
+        }
+
+        Thread {
+            id    : NEXT_ID.fetch_add(1, Ordering::SeqCst),
+            state : State::Ready,
+            ticks : 0,
+            idle  : false,
+            wakeup : false,
+            stack : Some(stack),
+            entry : Some(entry),
+            process : None,
+            fpu   : fpu::State::new(),
+            name, prio, rsp, 
+        }
+    }
+
+    pub default fn id(&self) -> usize {
+        self.id
+    }
+
+    pub default fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub default fn priority(&self) -> Priority {
+        self.prio
+    }
+
+    pub default fn state(&self) -> State {
+        self.state
+    }
+
+    /// Milliseconds spent running.
+    pub default fn cpu_time(&self) -> usize {
+        pit::ticks_to_ms(self.ticks)
+    }
+
+    pub fn process(&self) -> Option<&Arc<Process>> {
+        self.process.as_ref()
+    }
+}
+
+/// Runs `f` on the scheduler with interrupts disabled, so 
+/// the timer can't preempt while the lock is held.
+fn with_scheduler<F, R>(f : F) -> R 
+where
+    F : FnOnce(&mut Scheduler) -> R
+{
+    let e = interrupt::save_disable();
+    let r = f(&mut SCHEDULER.lock());
+    interrupt::restore(e);
+    r
+}
+
+/// First code executed by every spawned thread.
+extern "C" fn thread_start() -> ! {
+    let f = with_scheduler(|s| s.current_mut().entry.take())
+        .expect("thread started without an entry point");
+
+    f();
+    exit()
+}
+
+fn new_thread<F>(name : &'static str, prio : Priority, f : F) -> Thread
+where
+    F : FnOnce() + Send + 'static
+{
+    let stack = OwnedStack::alloc(STACK_PAGES)
+        .expect("thread stack cannot be allocated");
+
+    Thread::new(name, prio, stack, Box::new(f))
+}
+
+/// Registers the boot flow of control as the first thread
+/// and creates the idle thread.
+pub fn init() {
+    once!("thread::init cannot be called twice");
+
+    let mut idle = new_thread("idle", Priority::Low, || loop {
+        reap();
+        unsafe { asm!("hlt") };
+    });
+    idle.idle = true;
+
+    with_scheduler(|s| s.init(Thread::boot(), idle));
+}
+
+/// Spawns a new thread running `f` with normal priority, returns its id.
+pub fn spawn<F>(name : &'static str, f : F) -> usize
+where
+    F : FnOnce() + Send + 'static
+{
+    spawn_with(name, Priority::Normal, f)
+}
+
+/// Spawns a new thread running `f` with the given priority, returns its id.
+pub fn spawn_with<F>(name : &'static str, prio : Priority, f : F) -> usize
+where
+    F : FnOnce() + Send + 'static
+{
+    reap();
+
+    let t  = new_thread(name, prio, f);
+    let id = t.id;
+
+    with_scheduler(|s| s.push(t));
+    id
+}
+
+/// Spawns a thread of the process `p` running `f`, the address 
+/// space of `p` is loaded whenever the thread is switched in.
+pub fn spawn_in<F>(name : &'static str, p : Arc<Process>, f : F) -> usize
+where
+    F : FnOnce() + Send + 'static
+{
+    reap();
+
+    let mut t = new_thread(name, Priority::Normal, f);
+    t.process = Some(p);
+    let id = t.id;
+
+    with_scheduler(|s| s.push(t));
+    id
+}
+
+/// Frees exited threads with their stacks. Dropping the last thread of 
+/// a process frees its address space, which must not happen under the 
+/// scheduler lock.
+pub fn reap() {
+    let dead = with_scheduler(|s| s.take_dead());
+    drop(dead);
+}
+
+/// Switches to the next ready thread, if the current one isn't
+/// running anymore it's parked according to its state.
+fn reschedule() {
+    let e = interrupt::save_disable();
+
+    let switch = SCHEDULER.lock().switch();
+
+    if let Some((old_rsp, new_rsp)) = switch {
+        unsafe { switch_context(old_rsp, new_rsp) };
+    }
+
+    interrupt::restore(e);
+}
+
+/// Gives up the CPU to the next ready thread.
+pub fn yield_now() {
+    reschedule();
+}
+
+/// Terminates the current thread.
+pub fn exit() -> ! {
+    with_scheduler(|s| s.current_mut().state = State::Dead);
+    reschedule();
+    unreachable!("dead thread was resumed");
+}
+
+/// Puts the current thread to sleep for at least `ms` milliseconds.
+pub fn sleep(ms : usize) {
+    let until = pit::ticks() + pit::ms_to_ticks(ms);
+    with_scheduler(|s| s.current_mut().state = State::Sleeping(until));
+    reschedule();
+}
+
+/// Blocks the current thread until someone calls `unblock` on it,
+/// returns at once if that happened since the last `block`.
+pub fn block() {
+    if with_scheduler(|s| s.block()) {
+        reschedule();
+    }
+}
+
+/// Makes the blocked thread `id` ready again.
+pub fn unblock(id : usize) -> bool {
+    with_scheduler(|s| s.unblock(id))
+}
+
+/// Returns the id of the running thread.
+pub fn current() -> usize {
+    with_scheduler(|s| s.current_mut().id)
+}
+
+/// Returns the id and name of the running thread, or `None` if the 
+/// scheduler is busy. Usable from exception handlers.
+pub fn try_current() -> Option<(usize, &'static str)> {
+    let e = interrupt::save_disable();
+    let r = SCHEDULER.try_lock().and_then(|s| s.current().map(|t| (t.id, t.name)));
+    interrupt::restore(e);
+    r
+}
+
+/// Returns the process of the running thread, `None` for kernel 
+/// threads or if the scheduler is busy. Usable from exception handlers.
+pub fn current_process() -> Option<Arc<Process>> {
+    let e = interrupt::save_disable();
+    let r = SCHEDULER.try_lock().and_then(|s| s.current().and_then(|t| t.process.clone()));
+    interrupt::restore(e);
+    r
+}
+
+/// Called from the timer interrupt, preempts the running 
+/// thread once its slice is over unless preemption is disabled.
+pub fn tick(now : usize) {
+    if SCHEDULER.lock().tick(now) && percpu::preemptible() {
+        reschedule();
+    }
+}
+
+/// Prints all threads with their state and CPU time.
+pub fn ps() {
+    println!("{:>4} {:<12} {:<7} {:<14} {:>8}", "id", "name", "prio", "state", "cpu ms");
+
+    with_scheduler(|s| s.for_each(|t| {
+        println!("{:>4} {:<12} {:<7} {:<14} {:>8}", 
+                 t.id, t.name, format!("{:?}", t.prio), format!("{:?}", t.state), t.cpu_time());
+    }));
+}
@@ -0,0 +1,228 @@
+// -*- mode: rust; -*-
+
+use spin::Mutex;
+
+use alloc::{ Vec
+           , VecDeque
+           , boxed::Box };
+
+use kernel::{ fpu, interrupt, smp::percpu };
+
+use super::{ Thread
+           , State
+           , PRIORITIES };
+
+/// Number of ticks a thread may run before being preempted.
+pub const TIME_SLICE : usize = 5;
+
+lazy_static! {
+    pub static ref SCHEDULER : Mutex<Scheduler> = Mutex::new(Scheduler::new());
+}
+
+pub struct Scheduler {
+    /// running thread
+    cur      : Option<Box<Thread>>,
+    /// threads waiting for the CPU, one queue per priority
+    ready    : [VecDeque<Box<Thread>> ; PRIORITIES],
+    /// threads waiting for a deadline
+    sleeping : Vec<Box<Thread>>,
+    /// threads waiting for `unblock`
+    blocked  : Vec<Box<Thread>>,
+    /// runs when nothing else is ready
+    idle     : Option<Box<Thread>>,
+    /// exited threads, freed by `reap` once nothing runs on them
+    dead     : Vec<Box<Thread>>,
+    /// ticks left in the slice of the running thread
+    slice    : usize,
+}
+
+impl Scheduler {
+    fn new() -> Scheduler {
+        Scheduler { 
+            cur      : None, 
+            ready    : [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            sleeping : Vec::new(),
+            blocked  : Vec::new(),
+            idle     : None,
+            dead     : Vec::new(),
+            slice    : TIME_SLICE,
+        }
+    }
+
+    pub fn init(&mut self, boot : Thread, idle : Thread) {
+        self.cur  = Some(Box::new(boot));
+        self.idle = Some(Box::new(idle));
+
+        fpu::switch_to(&mut self.current_mut().fpu);
+    }
+
+    pub fn current(&self) -> Option<&Thread> {
+        self.cur.as_ref().map(|t| &**t)
+    }
+
+    pub fn current_mut(&mut self) -> &mut Thread {
+        self.cur.as_mut().expect("threads are not initialized")
+    }
+
+    pub fn push(&mut self, t : Thread) {
+        self.make_ready(Box::new(t));
+    }
+
+    fn make_ready(&mut self, mut t : Box<Thread>) {
+        t.state = State::Ready;
+        self.ready[t.prio as usize].push_back(t);
+    }
+
+    /// Highest priority among the ready threads.
+    fn top_priority(&self) -> Option<usize> {
+        (0..PRIORITIES).rev().find(|&p| !self.ready[p].is_empty())
+    }
+
+    /// Marks the running thread as blocked, unless a wake up for it 
+    /// arrived already. Returns `false` in that case.
+    pub fn block(&mut self) -> bool {
+        let cur = self.current_mut();
+
+        if cur.wakeup {
+            cur.wakeup = false;
+            return false;
+        }
+
+        cur.state = State::Blocked;
+        true
+    }
+
+    /// Moves the thread `id` from the blocked list to its ready queue.
+    /// A thread which didn't get to block yet keeps the wake up for
+    /// its next `block`.
+    pub fn unblock(&mut self, id : usize) -> bool {
+        if let Some(i) = self.blocked.iter().position(|t| t.id == id) {
+            let t = self.blocked.remove(i); 
+            self.make_ready(t); 
+            return true;
+        }
+
+        let t = self.cur.iter_mut()
+            .chain(self.ready.iter_mut().flat_map(|q| q.iter_mut()))
+            .find(|t| t.id == id);
+
+        match t {
+            Some(t) => { t.wakeup = true; true }
+            None    => false,
+        }
+    }
+
+    /// Accounts a timer tick to the running thread and wakes up
+    /// sleepers whose deadline passed. Returns `true` if the running 
+    /// thread should be preempted.
+    pub fn tick(&mut self, now : usize) -> bool {
+        let mut i = 0;
+        while i < self.sleeping.len() {
+            match self.sleeping[i].state {
+                State::Sleeping(until) if until <= now => {
+                    let t = self.sleeping.remove(i);
+                    self.make_ready(t);
+                }
+                _ => i += 1,
+            }
+        }
+
+        let is_idle = self.cur.as_ref().map_or(false, |c| c.idle);
+        let prio = {
+            let cur = self.current_mut();
+            cur.ticks += 1;
+            cur.prio as usize
+        };
+
+        if self.slice > 0 { self.slice -= 1; }
+
+        match self.top_priority() {
+            Some(_) if is_idle => true,
+            Some(p) if p > prio => true,
+            Some(p) if p == prio => self.slice == 0,
+            _ => false,
+        }
+    }
+
+    /// Makes the next ready thread current and returns the save slot 
+    /// for the stack pointer of the previous one with the stack 
+    /// pointer to resume. Returns `None` if there is nothing to switch to.
+    /// A thread which stopped running is always switched away from, to 
+    /// the idle thread if nothing else is ready.
+    pub fn switch(&mut self) -> Option<(*mut usize, usize)> {
+        // the idle thread has nothing to wait for, it keeps running
+        // rather than leaving the CPU without a thread
+        if let Some(ref mut c) = self.cur {
+            if c.idle { c.state = State::Running; }
+        }
+
+        let runnable = self.cur.as_ref().map_or(false, |c| c.state == State::Running);
+
+        let next = match self.top_priority() {
+            Some(p) => self.ready[p].pop_front(),
+            None if runnable => None,
+            None => Some(self.idle.take().expect("idle thread is missing")),
+        };
+
+        let mut next = match next {
+            Some(t) => t,
+            None    => { self.slice = TIME_SLICE; return None; }
+        };
+
+        let mut prev = self.cur.take().expect("threads are not initialized");
+        let old_rsp  = &mut prev.rsp as *mut usize;
+
+        let state = prev.state;
+
+        if prev.idle { 
+            self.idle = Some(prev); 
+        } else {
+            match state {
+                State::Dead        => self.dead.push(prev),
+                State::Sleeping(_) => self.sleeping.push(prev),
+                State::Blocked     => self.blocked.push(prev),
+                State::Running 
+              | State::Ready       => self.make_ready(prev),
+            }
+        }
+
+        next.state = State::Running;
+        let new_rsp = next.rsp;
+
+        // interrupts from ring 3 land on the kernel stack of the thread
+        if let Some(ref st) = next.stack {
+            interrupt::set_kernel_stack(st.top());
+        }
+
+        percpu::set_thread(next.id);
+        fpu::switch_to(&mut next.fpu);
+
+        // kernel threads keep running on the tables loaded before
+        if let Some(ref p) = next.process {
+            p.space().activate();
+        }
+
+        self.cur = Some(next);
+        self.slice = TIME_SLICE;
+
+        Some((old_rsp, new_rsp))
+    }
+
+    /// Hands the exited threads over to the caller.
+    pub fn take_dead(&mut self) -> Vec<Box<Thread>> {
+        ::core::mem::replace(&mut self.dead, Vec::new())
+    }
+
+    /// Calls `f` on every thread known to the scheduler.
+    pub fn for_each<F>(&self, mut f : F) 
+    where 
+        F : FnMut(&Thread)
+    {
+        self.cur.iter()
+            .chain(self.ready.iter().flat_map(|q| q.iter()))
+            .chain(self.sleeping.iter())
+            .chain(self.blocked.iter())
+            .chain(self.idle.iter())
+            .for_each(|t| f(t));
+    }
+}
@@ -0,0 +1,36 @@
+// -*- mode: rust; -*-
+
+//! # Ring 3 entry
+
+use kernel::interrupt;
+
+/// Flags user code starts with, interrupts enabled.
+const USER_RFLAGS : u64 = 0x202;
+
+/// Leaves the kernel and continues at `entry` in ring 3 with the stack 
+/// pointer set to `stack`. Both have to be mapped `USER_ACCESSIBLE`, 
+/// interrupts from user code come back on the stack given to 
+/// `interrupt::set_kernel_stack`. The kernel GS base is swapped out 
+/// right before leaving, see `smp::percpu`.
+pub unsafe fn jump_to_user(entry : usize, stack : usize) -> ! {
+    let s = interrupt::selectors();
+
+    let cs = s.user_code.0 as u64;
+    let ss = s.user_data.0 as u64;
+
+    // no interrupt may see the user GS base while still in ring 0
+    asm!("cli
+          mov ds, $0
+          mov es, $0
+          push $0
+          push $1
+          push $2
+          push $3
+          push $4
+          swapgs
+          iretq"
+         :: "r"(ss), "r"(stack), "r"(USER_RFLAGS), "r"(cs), "r"(entry)
+         : "memory" : "intel", "volatile");
+
+    unreachable!();
+}
@@ -2,12 +2,14 @@
 
 extern crate spin;
 
-use spin::Mutex;
 use core::{ fmt, ptr::Unique };
 
+use kernel::{ sync::SpinLock
+            , mem::globals::VGA_BUFFER };
+
 #[cfg(feature = "use_spin")]
-pub static WRITER : Mutex<Writer> = 
-    Mutex::new(Writer::new(VGAConfig::new()));
+pub static WRITER : SpinLock<Writer> = 
+    SpinLock::new(Writer::new(VGAConfig::new()));
 
 #[derive(Debug, Default)]
 pub struct VGAConfig {
@@ -78,7 +80,7 @@ pub struct Writer {
 impl Writer {
     const fn new(config : VGAConfig) -> Writer {
         Writer {
-            buffer  : unsafe { Unique::new_unchecked(0xb8000 as *mut _) },
+            buffer  : unsafe { Unique::new_unchecked(VGA_BUFFER as *mut _) },
             cur_cmn : 0,
             config,
         }